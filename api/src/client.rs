@@ -0,0 +1,430 @@
+use async_stream::try_stream;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use http::header::{RETRY_AFTER, USER_AGENT};
+use http::StatusCode;
+
+use crate::error::Error;
+use crate::models::{RawTransactionsGrid, Transaction, TransactionsGrid};
+use crate::query::TransactionsQuery;
+use crate::region::Region;
+
+pub const DEFAULT_API_URL: &str = r#"https://www.g-portal.com"#;
+pub const DEFAULT_USER_AGENT: &str =
+    r#"Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:82.0) Gecko/20100101 Firefox/82.0"#;
+
+/// Where to stop walking the transaction history.
+///
+/// With the default (descending) order this means reaching an older transaction, with an
+/// ascending query a newer one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopAt {
+    /// Walk every page until the history is exhausted.
+    End,
+    /// Stop once the transaction with this ID (or one past it) is reached. The transaction itself is not yielded.
+    TransactionId(String),
+    /// Stop once a transaction at or past this time is reached.
+    Time(DateTime<Utc>),
+}
+
+impl StopAt {
+    fn is_reached(&self, transaction: &Transaction, ascending: bool) -> bool {
+        match self {
+            StopAt::End => false,
+            StopAt::TransactionId(id) => {
+                match (transaction.id.parse::<u64>(), id.parse::<u64>()) {
+                    (Ok(current), Ok(stop)) if ascending => current >= stop,
+                    (Ok(current), Ok(stop)) => current <= stop,
+                    _ => &transaction.id == id,
+                }
+            }
+            StopAt::Time(time) if ascending => transaction.time_to_utc() >= *time,
+            StopAt::Time(time) => transaction.time_to_utc() <= *time,
+        }
+    }
+}
+
+/// Client for the G-Portal website API.
+///
+/// Owns a pooled `reqwest::Client`, so a single instance should be kept around and reused
+/// between polls instead of being created for every request.
+#[derive(Debug, Clone)]
+pub struct GPortalClient {
+    client: reqwest::Client,
+    base_url: String,
+    region: Region,
+    user_agent: String,
+}
+
+impl Default for GPortalClient {
+    fn default() -> Self {
+        GPortalClient::new()
+    }
+}
+
+impl GPortalClient {
+    pub fn new() -> Self {
+        GPortalClient {
+            client: reqwest::Client::new(),
+            base_url: DEFAULT_API_URL.to_string(),
+            region: Region::default(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Fetches the first page of transactions.
+    pub async fn get_transactions(&self, access_token: &str) -> Result<TransactionsGrid, Error> {
+        self.get_transactions_page(access_token, &TransactionsQuery::new()).await
+    }
+
+    /// Walks the transaction history page by page, starting from `query.page`, until `stop_at`
+    /// is reached or every page reported by the endpoint's `total` has been fetched.
+    ///
+    /// A transaction arriving during the walk shifts the rows onto the next page, so rows that
+    /// were already yielded are skipped.
+    pub fn transactions<'a>(
+        &'a self,
+        access_token: &'a str,
+        query: TransactionsQuery,
+        stop_at: StopAt,
+    ) -> impl Stream<Item = Result<Transaction, Error>> + 'a {
+        try_stream! {
+            let mut query = query;
+            let mut fetched = query.page as u64 * query.page_size as u64;
+            let mut yielded = HashSet::new();
+
+            'pages: loop {
+                let data = self.get_transactions_page(access_token, &query).await?;
+                if data.transactions.is_empty() {
+                    break;
+                }
+
+                fetched += data.transactions.len() as u64;
+                for transaction in data.transactions {
+                    if stop_at.is_reached(&transaction, query.is_ascending()) {
+                        break 'pages;
+                    }
+                    if !yielded.insert(transaction.id.clone()) {
+                        continue;
+                    }
+
+                    yield transaction;
+                }
+
+                if fetched >= data.total {
+                    break;
+                }
+                query.page += 1;
+            }
+        }
+    }
+
+    /// Collects the transaction history until `stop_at` is reached.
+    pub async fn get_transactions_until(
+        &self,
+        access_token: &str,
+        query: TransactionsQuery,
+        stop_at: StopAt,
+    ) -> Result<Vec<Transaction>, Error> {
+        use futures::TryStreamExt;
+
+        self.transactions(access_token, query, stop_at).try_collect().await
+    }
+
+    pub async fn get_transactions_page(
+        &self,
+        access_token: &str,
+        query: &TransactionsQuery,
+    ) -> Result<TransactionsGrid, Error> {
+        let res = self
+            .client
+            .get(format!(
+                "{}/{}/profile/transactions/{}",
+                self.base_url, self.region.path(), query.path()
+            ))
+            .header(USER_AGENT, &self.user_agent)
+            .header("Origin", &self.base_url)
+            .header(
+                "Referer",
+                format!("{}/{}/profile/payments/transactions", self.base_url, self.region.path()),
+            )
+            .header("X-Application-Name", "VueJS")
+            .header("X-Requested-With", "XMLHttpRequest")
+            .header("Cookie", format!("gp_at={}", access_token))
+            .send()
+            .await?;
+
+        let status = res.status();
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs);
+
+        let data_str = res.text().await?;
+        //println!("{}", data_str);
+
+        match status {
+            StatusCode::OK => (),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                debug!("GPortal rejected the access token with {}", status);
+                return Err(Error::Unauthorized);
+            }
+            StatusCode::TOO_MANY_REQUESTS => {
+                debug!("GPortal rate limited fetching the transactions");
+                return Err(Error::RateLimited { retry_after });
+            }
+            _ => {
+                debug!("Failed to fetch transactions from GPortal");
+                return Err(Error::Status { status, body: data_str });
+            }
+        }
+
+        let raw: RawTransactionsGrid = serde_json::from_str(&data_str)?;
+        let data = TransactionsGrid::from_raw(raw, self.region)?;
+        trace!("TransactionsGrid: {:#?}", data);
+
+        for transaction in data.transactions.iter().filter(|t| t.amount.currency != self.region.currency_code()) {
+            warn!(
+                "Transaction {} amount '{}' is not in the expected currency {} of region {}",
+                transaction.id,
+                transaction.amount,
+                self.region.currency_code(),
+                self.region
+            );
+        }
+
+        debug!("Fetched {} transactions from GPortal (page {}, total {})", data.transactions.len(), query.page, data.total);
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{SortColumn, SortOrder};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_get_transactions_from_mock_server() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/eur/profile/transactions/0/25/-/-"))
+            .and(header("Cookie", "gp_at=test-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{ "grid": [["14500000", "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-01T21:50:01+02:00"]], "total": 1 }"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri());
+        let data = client.get_transactions("test-token").await.unwrap();
+
+        assert_eq!(data.transactions.len(), 1);
+        assert_eq!(data.get_donations()[0].id, "14500000");
+    }
+
+    fn transactions_page(first_id: u64, count: u64, total: u64) -> String {
+        let rows: Vec<String> = (0..count)
+            .map(|i| {
+                format!(
+                    r#"["{}", "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-01T21:50:01+02:00"]"#,
+                    first_id - i
+                )
+            })
+            .collect();
+
+        format!(r#"{{ "grid": [{}], "total": {} }}"#, rows.join(","), total)
+    }
+
+    #[tokio::test]
+    async fn test_transactions_walks_all_pages() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/eur/profile/transactions/0/25/-/-"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(transactions_page(30, 25, 30)))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/eur/profile/transactions/1/25/-/-"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(transactions_page(5, 5, 30)))
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri());
+
+        let all = client
+            .get_transactions_until("test-token", TransactionsQuery::new(), StopAt::End)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 30);
+        assert_eq!(all.first().unwrap().id, "30");
+        assert_eq!(all.last().unwrap().id, "1");
+
+        let newer = client
+            .get_transactions_until("test-token", TransactionsQuery::new(), StopAt::TransactionId("3".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(newer.len(), 27);
+        assert_eq!(newer.last().unwrap().id, "4");
+    }
+
+    #[tokio::test]
+    async fn test_transactions_skips_shifted_rows() {
+        let server = MockServer::start().await;
+
+        // Transaction 31 arrived after the first page was fetched, pushing 6 onto the second page
+        Mock::given(method("GET"))
+            .and(path("/eur/profile/transactions/0/25/-/-"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(transactions_page(30, 25, 30)))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/eur/profile/transactions/1/25/-/-"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(transactions_page(6, 6, 31)))
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri());
+        let all = client
+            .get_transactions_until("test-token", TransactionsQuery::new(), StopAt::End)
+            .await
+            .unwrap();
+
+        assert_eq!(all.len(), 30);
+        assert_eq!(all.iter().filter(|t| t.id == "6").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_transactions_ascending_stops_at_newer_id() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/eur/profile/transactions/0/25/id/asc"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{ "grid": [
+                    ["1", "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-01T21:50:01+02:00"],
+                    ["2", "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-02T21:50:01+02:00"],
+                    ["3", "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-03T21:50:01+02:00"]
+                ], "total": 3 }"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri());
+        let query = TransactionsQuery::new().sort_by(SortColumn::Id, SortOrder::Ascending);
+
+        let older = client
+            .get_transactions_until("test-token", query, StopAt::TransactionId("3".to_string()))
+            .await
+            .unwrap();
+        let ids: Vec<&str> = older.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_us_region() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/us/profile/transactions/0/25/-/-"))
+            .and(header("Referer", format!("{}/us/profile/payments/transactions", server.uri()).as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{ "grid": [["14500000", "Donation from poorGuy - Purpose: PoorGuy", "$1,001.49", "2022-10-01T21:50:01+02:00"]], "total": 1 }"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri()).with_region(Region::Us);
+        let donation = &client.get_transactions("test-token").await.unwrap().get_donations()[0];
+
+        assert_eq!(donation.amount.currency, "USD");
+        assert_eq!(donation.amount.to_string(), "$1001.49");
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_error_status() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("Unauthorized"))
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri());
+
+        assert!(matches!(client.get_transactions("expired-token").await, Err(Error::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_rate_limited() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri());
+
+        match client.get_transactions("test-token").await {
+            Err(Error::RateLimited { retry_after }) => assert_eq!(retry_after, Some(Duration::from_secs(30))),
+            other => panic!("Expected rate limit error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_malformed_row() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{ "grid": [
+                    ["14500001", "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-01T21:50:01+02:00"],
+                    ["14500000", "Donation from poorGuy - Purpose: PoorGuy"]
+                ], "total": 2 }"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let client = GPortalClient::new().with_base_url(server.uri());
+
+        let err = client.get_transactions("test-token").await.unwrap_err();
+        assert!(err.is_schema_error());
+        assert!(matches!(err, Error::MalformedRow { row_index: 1, .. }));
+    }
+}
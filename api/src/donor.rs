@@ -0,0 +1,141 @@
+use std::fmt;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Who a donation came from, as shown in the transaction description
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Donor {
+    Username(String),
+    /// Donors without a G-Portal account show up with their email address
+    Email(String),
+    Unknown,
+}
+
+impl Donor {
+    pub fn parse(value: &str) -> Donor {
+        let value = value.trim();
+
+        if value.is_empty() {
+            Donor::Unknown
+        } else if is_email(value) {
+            Donor::Email(value.to_string())
+        } else {
+            Donor::Username(value.to_string())
+        }
+    }
+
+    /// Identifier as it appears in the transaction, never use this for public output.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Donor::Username(value) | Donor::Email(value) => value,
+            Donor::Unknown => "Unknown",
+        }
+    }
+
+    /// Name safe to show publicly, email addresses are masked.
+    pub fn public_name(&self) -> String {
+        match self {
+            Donor::Email(email) => mask_email(email),
+            _ => self.as_str().to_string(),
+        }
+    }
+
+    pub fn is_email(&self) -> bool {
+        matches!(self, Donor::Email(_))
+    }
+}
+
+/// Shows the public name, so email addresses don't end up in channels by accident
+impl fmt::Display for Donor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.public_name())
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    let re = Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$").unwrap();
+    re.is_match(value)
+}
+
+/// Masks everything but the first character of the local part and the domain name,
+/// `xfileFIN@xfileFIN.com` becomes `x***@x***.com`.
+pub fn mask_email(email: &str) -> String {
+    fn mask(part: &str) -> String {
+        match part.chars().next() {
+            Some(first) => format!("{}***", first),
+            None => String::new(),
+        }
+    }
+
+    match email.split_once('@') {
+        Some((local, domain)) => match domain.rsplit_once('.') {
+            Some((name, tld)) => format!("{}@{}.{}", mask(local), mask(name), tld),
+            None => format!("{}@{}", mask(local), mask(domain)),
+        },
+        None => mask(email),
+    }
+}
+
+/// Donor and purpose of a `Donation from <donor> - Purpose: <purpose>` description
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DonationDescription {
+    pub donor: Donor,
+    /// Full purpose, line breaks preserved
+    pub purpose: String,
+}
+
+impl DonationDescription {
+    /// Splits at the first ` - Purpose: `, as donors can write anything in the purpose
+    /// but G-Portal usernames and emails can't contain the separator.
+    pub fn parse(description: &str) -> Option<DonationDescription> {
+        let re = Regex::new(r"(?s)^Donation from (.+?)(?: - Purpose: (.*))?$").unwrap();
+        let caps = re.captures(description)?;
+
+        Some(DonationDescription {
+            donor: Donor::parse(caps.get(1).map_or("", |m| m.as_str())),
+            purpose: caps.get(2).map_or("", |m| m.as_str()).to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_username_and_multiline_purpose() {
+        let donation = DonationDescription::parse(
+            "Donation from T3stingMan - Purpose: soldiername: xfileFIN\nDiscord tag: xfileFIN#2811",
+        )
+        .unwrap();
+
+        assert_eq!(donation.donor, Donor::Username("T3stingMan".to_string()));
+        assert_eq!(donation.purpose, "soldiername: xfileFIN\nDiscord tag: xfileFIN#2811");
+    }
+
+    #[test]
+    fn test_parse_email_donor() {
+        let donation =
+            DonationDescription::parse("Donation from xfileFIN@xfileFIN.com - Purpose: VIP for xfileFIN").unwrap();
+
+        assert!(donation.donor.is_email());
+        assert_eq!(donation.donor.as_str(), "xfileFIN@xfileFIN.com");
+        assert_eq!(donation.donor.to_string(), "x***@x***.com");
+        assert_eq!(donation.purpose, "VIP for xfileFIN");
+    }
+
+    #[test]
+    fn test_parse_separator_in_purpose() {
+        let donation = DonationDescription::parse("Donation from poorGuy - Purpose: VIP - Purpose: fun").unwrap();
+        assert_eq!(donation.donor.as_str(), "poorGuy");
+        assert_eq!(donation.purpose, "VIP - Purpose: fun");
+
+        let donation = DonationDescription::parse("Donation from poorGuy").unwrap();
+        assert_eq!(donation.donor.as_str(), "poorGuy");
+        assert_eq!(donation.purpose, "");
+
+        assert!(DonationDescription::parse("Gamecloud Basic - Gamecloud Basic").is_none());
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use http::StatusCode;
+
+/// Errors returned by the G-Portal API client.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("HTTP request to G-Portal failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    /// The access token was rejected, the session has most likely expired
+    #[error("G-Portal rejected the access token")]
+    Unauthorized,
+
+    #[error("Rate limited by G-Portal (retry after {retry_after:?})")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("G-Portal responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+
+    #[error("Malformed transaction row {row_index}: {source}")]
+    MalformedRow {
+        row_index: usize,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("Invalid amount '{value}': {reason}")]
+    ParseAmount { value: String, reason: String },
+
+    #[error("Invalid time '{value}': {source}")]
+    ParseTime {
+        value: String,
+        #[source]
+        source: chrono::ParseError,
+    },
+
+    /// The response didn't look like what the client expects, G-Portal has probably changed the API
+    #[error("Unexpected response schema: {0}")]
+    UnexpectedSchema(String),
+
+    #[error("Unknown G-Portal region '{0}'")]
+    UnknownRegion(String),
+}
+
+impl Error {
+    /// Whether the error means that the poller should get a new access token before retrying.
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(self, Error::Unauthorized)
+    }
+
+    /// Whether the error is caused by G-Portal returning something the client can't parse.
+    pub fn is_schema_error(&self) -> bool {
+        matches!(
+            self,
+            Error::MalformedRow { .. }
+                | Error::ParseAmount { .. }
+                | Error::ParseTime { .. }
+                | Error::UnexpectedSchema(_)
+        )
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::UnexpectedSchema(err.to_string())
+    }
+}
@@ -1,57 +1,24 @@
 #[macro_use] extern crate log;
 
+pub mod client;
+pub mod donor;
+pub mod error;
 pub mod models;
-
-use http::{header::USER_AGENT, StatusCode};
+pub mod money;
+pub mod pricing;
+pub mod purpose;
+pub mod query;
+pub mod region;
+
+pub use client::{GPortalClient, StopAt};
+pub use donor::{DonationDescription, Donor};
+pub use error::Error;
 pub use models::*;
-
-const API_URL: &str = r#"https://www.g-portal.com"#;
-const USER_AGENT_STR: &str =
-    r#"Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:82.0) Gecko/20100101 Firefox/82.0"#;
-
-pub async fn get_transactions(access_token: &str) -> Result<TransactionsGrid, anyhow::Error> {
-    let client = reqwest::Client::new();
-
-    let page = 0;
-    let page_size = 25;
-    let sort_column = "-"; // - (default) | id | description | activity | time
-    let sort_order = "-"; // - (default) | asc (ascending) | desc (descending)
-
-    let res = client
-        .get(format!(
-            "{}/eur/profile/transactions/{}/{}/{}/{}",
-            API_URL, page, page_size, sort_column, sort_order
-        ))
-        .header(USER_AGENT, USER_AGENT_STR)
-        .header("Host", "www.g-portal.com")
-        .header("Origin", "https://www.g-portal.com")
-        .header(
-            "Referer",
-            "https://www.g-portal.com/eur/profile/payments/transactions",
-        )
-        .header("X-Application-Name", "VueJS")
-        .header("X-Requested-With", "XMLHttpRequest")
-        .header("Cookie", format!("gp_at={}", access_token))
-        .send()
-        .await?;
-
-    let status = res.status();
-
-    let data_str = res.text().await?;
-    //println!("{}", data_str);
-
-    if status != StatusCode::OK {
-        debug!("Failed to fetch transactions from GPortal");
-        return Err(anyhow::anyhow!(data_str));
-    }
-
-    let data: TransactionsGrid = serde_json::from_str(&data_str)?;
-    trace!("TransactionsGrid: {:#?}", data);
-
-    debug!("Fetched {} transactions from GPortal", data.grid.len());
-
-    Ok(data)
-}
+pub use money::Money;
+pub use pricing::{PricingPolicy, PricingTable, RoundingMode};
+pub use purpose::{DonationPurpose, PurposeAliases, PurposeField, PurposeParser};
+pub use query::{SortColumn, SortOrder, TransactionsQuery};
+pub use region::Region;
 
 #[cfg(test)]
 mod tests {
@@ -99,9 +66,20 @@ mod tests {
             "total": 112
         }"#;
 
-        let data: TransactionsGrid = serde_json::from_str(&data_str).unwrap();
+        let data: TransactionsGrid = serde_json::from_str(data_str).unwrap();
         println!("TransactionsGrid: {:#?}", data);
 
+        assert_eq!(data.total, 112);
+        assert_eq!(data.transactions[1].kind, TransactionKind::ServiceCharge);
+        assert_eq!(data.transactions[1].amount.to_string(), "-32.70 €");
+
+        let purpose = data.transactions[3].purpose(&PurposeParser::default());
+        assert_eq!(purpose.soldier_name.as_deref(), Some("xfileFIN"));
+        assert_eq!(purpose.discord_tag.as_deref(), Some("xfileFIN#2811"));
+        assert_eq!(data.transactions[2].get_donator_and_purpose().0.public_name(), "x***@x***.com");
+
+        let pricing = PricingPolicy::default();
+
         println!("\nDonations:");
         let donations = data.get_donations();
         for donation in &donations {
@@ -109,11 +87,11 @@ mod tests {
                 "{} - {} - {} ({} days) - {}",
                 donation.id,
                 donation.description,
-                donation.amount_to_currency().to_string(),
-                donation.amount_to_days(),
+                donation.amount,
+                donation.amount_to_days(&pricing),
                 donation.time_to_utc()
             );
         }
-        println!("");
+        println!();
     }
 }
@@ -1,86 +1,158 @@
-use currency_rs::{Currency};
-use regex::Regex;
+use std::convert::TryFrom;
+
+use chrono::{DateTime, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
 
+use crate::donor::{DonationDescription, Donor};
+use crate::error::Error;
+use crate::money::Money;
+use crate::pricing::PricingPolicy;
+use crate::purpose::{DonationPurpose, PurposeParser};
+use crate::region::Region;
+
+/// Transactions grid as returned by the endpoint, every row being `[id, description, amount, time]`
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct TransactionsGrid {
+pub struct RawTransactionsGrid {
     pub grid: Vec<Vec<String>>,
+    #[serde(default)]
+    pub total: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "RawTransactionsGrid")]
+pub struct TransactionsGrid {
+    pub transactions: Vec<Transaction>,
+    /// Total number of transactions in the account, over all pages
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Donation,
+    /// Payment for a service, e.g. "Gamecloud Basic - Gamecloud Basic"
+    ServiceCharge,
+    TopUp,
+    Refund,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: String,
     pub description: String,
-    pub amount: String,
-    pub time: String,
+    pub amount: Money,
+    pub time: DateTime<FixedOffset>,
+    pub kind: TransactionKind,
 }
 
 impl TransactionsGrid {
-    pub fn get_donations(&self) -> Vec<Transaction> {
-        let results: Vec<Transaction> = self.grid
+    /// Parses the raw rows, expecting the amounts to be in the currency of `region`.
+    pub fn from_raw(raw: RawTransactionsGrid, region: Region) -> Result<Self, Error> {
+        let transactions = raw
+            .grid
             .iter()
-            .filter_map(|p| match p[1].starts_with("Donation from") {
-                true => {
-                    Some(Transaction {
-                        id: p[0].to_string(),
-                        description: p[1].to_string(),
-                        amount: p[2].to_string(),
-                        time: p[3].to_string(),
-                    })
-                },
-                false => None,
+            .enumerate()
+            .map(|(row_index, row)| {
+                Transaction::from_row(row, region).map_err(|err| Error::MalformedRow {
+                    row_index,
+                    source: Box::new(err),
+                })
             })
-            .collect();
+            .collect::<Result<Vec<_>, _>>()?;
 
-        results
+        Ok(TransactionsGrid {
+            transactions,
+            total: raw.total,
+        })
+    }
+
+    pub fn get_donations(&self) -> Vec<Transaction> {
+        self.transactions
+            .iter()
+            .filter(|p| p.is_donation())
+            .cloned()
+            .collect()
     }
 }
 
-impl Transaction {
-    pub fn get_donator_and_purpose(&self) -> (String, String) {
-        let re = Regex::new(r"Donation from (.*) - Purpose: (.*)").unwrap();
-        let capture_result = re.captures(&self.description);
+impl TryFrom<RawTransactionsGrid> for TransactionsGrid {
+    type Error = Error;
 
-        if capture_result.is_none() {
-            return ("Unknown".to_string(), self.description.to_string());
-        }
-        
-        let caps = capture_result.unwrap();
-        let donator = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        let purpose = caps.get(2).map_or("", |m| m.as_str()).to_string();
+    fn try_from(raw: RawTransactionsGrid) -> Result<Self, Self::Error> {
+        TransactionsGrid::from_raw(raw, Region::default())
+    }
+}
 
-        (donator, purpose)
+impl TransactionKind {
+    pub fn classify(description: &str, amount: &Money) -> TransactionKind {
+        let lowercase = description.to_lowercase();
+
+        if description.starts_with("Donation from") {
+            TransactionKind::Donation
+        } else if lowercase.contains("refund") {
+            TransactionKind::Refund
+        } else if lowercase.contains("top up") || lowercase.contains("top-up") || lowercase.contains("deposit") {
+            TransactionKind::TopUp
+        } else if amount.is_negative() {
+            TransactionKind::ServiceCharge
+        } else {
+            TransactionKind::Unknown
+        }
     }
+}
 
-    pub fn time_to_utc(&self) -> DateTime<Utc> {
-        self.time.parse::<DateTime<Utc>>().unwrap()
+impl Transaction {
+    /// Parses a single `[id, description, amount, time]` row of the transactions grid.
+    pub fn from_row<S: AsRef<str>>(row: &[S], region: Region) -> Result<Transaction, Error> {
+        if row.len() < 4 {
+            return Err(Error::UnexpectedSchema(format!(
+                "Expected 4 columns but got {}",
+                row.len()
+            )));
+        }
+
+        let description = row[1].as_ref().to_string();
+        let amount = Money::parse(row[2].as_ref(), region)?;
+        let time = DateTime::parse_from_rfc3339(row[3].as_ref()).map_err(|source| Error::ParseTime {
+            value: row[3].as_ref().to_string(),
+            source,
+        })?;
+
+        Ok(Transaction {
+            id: row[0].as_ref().to_string(),
+            kind: TransactionKind::classify(&description, &amount),
+            description,
+            amount,
+            time,
+        })
     }
 
-    pub fn amount_to_currency(&self) -> Currency {
-        Currency::new_string(&self.amount, None).unwrap()
+    pub fn is_donation(&self) -> bool {
+        self.kind == TransactionKind::Donation
     }
 
-    /**
-     * LSD specific donation pricing
-     * TODO: Make this configurable via config
-    */
-    pub fn amount_to_days(&self) -> i64 {
-        let currency = self.amount_to_currency();
-        let donation_amount = currency.value();
-
-        let days: f64;
-        if donation_amount >= 10.0 {
-            days = donation_amount / 10.0 * 90.0;
-        }
-        else if donation_amount >= 8.0 {
-            days = donation_amount / 8.0 * 60.0;
-        }
-        else {
-            days = donation_amount / 5.0 * 30.0;
+    /// Donor and full purpose of a donation. Anything that isn't a donation gives an unknown
+    /// donor with the description as the purpose.
+    pub fn get_donator_and_purpose(&self) -> (Donor, String) {
+        match DonationDescription::parse(&self.description) {
+            Some(donation) => (donation.donor, donation.purpose),
+            None => (Donor::Unknown, self.description.to_string()),
         }
+    }
+
+    /// Purpose of the donation split into the known fields
+    pub fn purpose(&self, parser: &PurposeParser) -> DonationPurpose {
+        parser.parse(&self.get_donator_and_purpose().1)
+    }
+
+    pub fn time_to_utc(&self) -> DateTime<Utc> {
+        self.time.with_timezone(&Utc)
+    }
 
-        days.round() as i64
+    /// VIP days the donated amount is worth with the given price list
+    pub fn amount_to_days(&self, pricing: &PricingPolicy) -> i64 {
+        pricing.days_for(&self.amount)
     }
 }
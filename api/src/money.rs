@@ -0,0 +1,113 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::region::Region;
+
+/// Symbols G-Portal uses in front of or after the amount, mapped to their ISO 4217 codes
+const CURRENCY_SYMBOLS: &[(&str, &str)] = &[("€", "EUR"), ("$", "USD"), ("£", "GBP")];
+
+/// Amount of money in a specific currency
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Money {
+    pub amount: Decimal,
+    /// ISO 4217 currency code, e.g. `EUR`
+    pub currency: String,
+}
+
+impl Money {
+    pub fn new(amount: Decimal, currency: impl Into<String>) -> Self {
+        Money {
+            amount,
+            currency: currency.into(),
+        }
+    }
+
+    /// Parses amounts like `150.00 €`, `-32.70 €`, `$1,001.49` or `5.00 EUR`.
+    ///
+    /// The currency is detected from the symbol or code in the string. An ambiguous symbol
+    /// (e.g. `$`) or a missing one resolves to the currency of the given region.
+    pub fn parse(value: &str, region: Region) -> Result<Money, Error> {
+        let value = value.trim();
+
+        let currency = if value.contains(region.currency_symbol())
+            || value.to_uppercase().contains(region.currency_code())
+        {
+            region.currency_code().to_string()
+        } else if let Some((_, code)) = CURRENCY_SYMBOLS.iter().find(|(symbol, _)| value.contains(symbol)) {
+            code.to_string()
+        } else if let Some(code) = value
+            .split_whitespace()
+            .find(|part| part.len() == 3 && part.chars().all(|c| c.is_ascii_alphabetic()))
+        {
+            code.to_uppercase()
+        } else {
+            region.currency_code().to_string()
+        };
+
+        let number: String = value
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+            .collect();
+        if number.is_empty() {
+            return Err(Error::ParseAmount {
+                value: value.to_string(),
+                reason: "no digits found".to_string(),
+            });
+        }
+
+        let amount = number.parse::<Decimal>().map_err(|err| Error::ParseAmount {
+            value: value.to_string(),
+            reason: err.to_string(),
+        })?;
+
+        Ok(Money { amount, currency })
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.amount.is_sign_negative() && !self.amount.is_zero()
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amount = self.amount.round_dp(2);
+        let symbol = CURRENCY_SYMBOLS
+            .iter()
+            .find(|(_, code)| *code == self.currency)
+            .map(|(symbol, _)| *symbol);
+
+        match (symbol, self.currency.as_str()) {
+            (Some(symbol), "EUR") => write!(f, "{:.2} {}", amount, symbol),
+            (Some(symbol), _) if amount.is_sign_negative() => write!(f, "-{}{:.2}", symbol, amount.abs()),
+            (Some(symbol), _) => write!(f, "{}{:.2}", symbol, amount),
+            (None, code) => write!(f, "{:.2} {}", amount, code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_amounts() {
+        let eur = Money::parse("150.00 €", Region::Eur).unwrap();
+        assert_eq!(eur, Money::new(Decimal::from(150), "EUR"));
+        assert_eq!(eur.to_string(), "150.00 €");
+
+        let charge = Money::parse("-32.70 €", Region::Eur).unwrap();
+        assert!(charge.is_negative());
+        assert_eq!(charge.amount, Decimal::from_str("-32.70").unwrap());
+
+        let usd = Money::parse("$1,001.49", Region::Us).unwrap();
+        assert_eq!(usd, Money::new(Decimal::from_str("1001.49").unwrap(), "USD"));
+        assert_eq!(usd.to_string(), "$1001.49");
+
+        assert_eq!(Money::parse("5.00 GBP", Region::Eur).unwrap().currency, "GBP");
+        assert!(Money::parse("€", Region::Eur).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::money::Money;
+
+/// How fractional days are turned into whole days
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    /// Half away from zero, e.g. 8.5 -> 9
+    Nearest,
+    Down,
+    Up,
+}
+
+/// Fixed package, a donation of exactly `amount` gives exactly `days`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Package {
+    pub amount: Decimal,
+    pub days: i64,
+}
+
+/// Linear rate used for donations of at least `from`, giving `days` for every `amount` donated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tier {
+    pub from: Decimal,
+    pub amount: Decimal,
+    pub days: i64,
+}
+
+/// Price list of a single currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PricingTable {
+    /// Donations below this give no days at all
+    pub minimum_amount: Decimal,
+    pub rounding: RoundingMode,
+    pub packages: Vec<Package>,
+    pub tiers: Vec<Tier>,
+}
+
+/// VIP pricing, converting donated amounts to VIP days.
+///
+/// The default is the LSD price list: 90 days per 10, 60 days per 8 and otherwise 30 days per 5.
+/// In TOML:
+///
+/// ```toml
+/// minimum_amount = 1
+/// rounding = "nearest"
+/// packages = [{ amount = 25, days = 365 }]
+/// tiers = [
+///     { from = 10, amount = 10, days = 90 },
+///     { from = 0, amount = 5, days = 30 },
+/// ]
+///
+/// [currencies.USD]
+/// tiers = [{ from = 0, amount = 6, days = 30 }]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PricingPolicy {
+    /// Table used for currencies that don't have their own table
+    #[serde(flatten)]
+    pub default: PricingTable,
+    /// Tables by ISO 4217 currency code
+    #[serde(default)]
+    pub currencies: HashMap<String, PricingTable>,
+}
+
+impl Default for PricingTable {
+    fn default() -> Self {
+        PricingTable {
+            minimum_amount: Decimal::ZERO,
+            rounding: RoundingMode::Nearest,
+            packages: Vec::new(),
+            tiers: vec![
+                Tier { from: Decimal::from(10), amount: Decimal::from(10), days: 90 },
+                Tier { from: Decimal::from(8), amount: Decimal::from(8), days: 60 },
+                Tier { from: Decimal::ZERO, amount: Decimal::from(5), days: 30 },
+            ],
+        }
+    }
+}
+
+impl PricingTable {
+    pub fn days_for(&self, amount: Decimal) -> i64 {
+        if amount <= Decimal::ZERO || amount < self.minimum_amount {
+            return 0;
+        }
+
+        if let Some(package) = self.packages.iter().find(|package| package.amount == amount) {
+            return package.days;
+        }
+
+        let tier = self
+            .tiers
+            .iter()
+            .filter(|tier| tier.from <= amount && !tier.amount.is_zero())
+            .max_by(|a, b| a.from.cmp(&b.from));
+
+        let days = match tier {
+            Some(tier) => amount / tier.amount * Decimal::from(tier.days),
+            None => return 0,
+        };
+
+        let strategy = match self.rounding {
+            RoundingMode::Nearest => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::Down => RoundingStrategy::ToZero,
+            RoundingMode::Up => RoundingStrategy::AwayFromZero,
+        };
+
+        days.round_dp_with_strategy(0, strategy).to_i64().unwrap_or(0)
+    }
+}
+
+impl PricingPolicy {
+    pub fn table_for(&self, currency: &str) -> &PricingTable {
+        self.currencies.get(currency).unwrap_or(&self.default)
+    }
+
+    pub fn days_for(&self, money: &Money) -> i64 {
+        self.table_for(&money.currency).days_for(money.amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn eur(amount: &str) -> Money {
+        Money::new(Decimal::from_str(amount).unwrap(), "EUR")
+    }
+
+    #[test]
+    fn test_default_policy_matches_lsd_pricing() {
+        let policy = PricingPolicy::default();
+
+        assert_eq!(policy.days_for(&eur("150.00")), 1350);
+        assert_eq!(policy.days_for(&eur("11.84")), 107);
+        assert_eq!(policy.days_for(&eur("8.00")), 60);
+        assert_eq!(policy.days_for(&eur("5.00")), 30);
+        assert_eq!(policy.days_for(&eur("1.49")), 9);
+        assert_eq!(policy.days_for(&eur("-32.70")), 0);
+    }
+
+    #[test]
+    fn test_policy_from_toml() {
+        let policy: PricingPolicy = toml::from_str(
+            r#"
+            minimum_amount = 2
+            rounding = "down"
+            packages = [{ amount = 25, days = 365 }]
+            tiers = [
+                { from = 10, amount = 10, days = 90 },
+                { from = 0, amount = 5, days = 30 },
+            ]
+
+            [currencies.USD]
+            tiers = [{ from = 0, amount = 6, days = 30 }]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.days_for(&eur("1.49")), 0);
+        assert_eq!(policy.days_for(&eur("25")), 365);
+        assert_eq!(policy.days_for(&eur("11.84")), 106);
+        assert_eq!(policy.days_for(&eur("9.99")), 59);
+
+        let usd = Money::new(Decimal::from(12), "USD");
+        assert_eq!(policy.days_for(&usd), 60);
+    }
+}
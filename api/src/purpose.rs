@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+
+/// Known fields donors put in the donation purpose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PurposeField {
+    SoldierName,
+    DiscordTag,
+    DiscordId,
+    SteamId,
+    EaId,
+    ServerName,
+}
+
+/// Donation purpose split into the known fields and the free text around them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DonationPurpose {
+    pub soldier_name: Option<String>,
+    pub discord_tag: Option<String>,
+    pub discord_id: Option<String>,
+    pub steam_id: Option<String>,
+    pub ea_id: Option<String>,
+    pub server_name: Option<String>,
+    /// Everything that wasn't recognised as a field, line breaks preserved
+    pub remainder: String,
+}
+
+/// Parses `key: value` lines from donation purposes, e.g.
+///
+/// ```text
+/// soldiername: xfileFIN
+/// Discord tag: xfileFIN#2811
+/// ```
+///
+/// Keys are matched case-insensitively ignoring spaces, underscores and dashes. The built-in
+/// aliases are always recognised, `aliases` adds more on top of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PurposeParser {
+    pub aliases: PurposeAliases,
+}
+
+/// Extra keys recognised for each field
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PurposeAliases {
+    pub soldier_name: Vec<String>,
+    pub discord_tag: Vec<String>,
+    pub discord_id: Vec<String>,
+    pub steam_id: Vec<String>,
+    pub ea_id: Vec<String>,
+    pub server_name: Vec<String>,
+}
+
+const DEFAULT_ALIASES: &[(PurposeField, &[&str])] = &[
+    (PurposeField::SoldierName, &["soldiername", "soldier", "ingamename", "ign", "playername", "player"]),
+    (PurposeField::DiscordTag, &["discordtag", "discord", "discordname", "discorduser", "discordusername"]),
+    (PurposeField::DiscordId, &["discordid", "discorduserid"]),
+    (PurposeField::SteamId, &["steamid", "steam", "steamid64"]),
+    (PurposeField::EaId, &["eaid", "ea", "eaaccount", "origin", "originid"]),
+    (PurposeField::ServerName, &["servername", "server"]),
+];
+
+fn normalize_key(key: &str) -> String {
+    key.chars()
+        .filter(|c| !c.is_whitespace() && *c != '_' && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_discord_id(value: &str) -> bool {
+    (17..=20).contains(&value.len()) && value.chars().all(|c| c.is_ascii_digit())
+}
+
+impl PurposeField {
+    pub const ALL: [PurposeField; 6] = [
+        PurposeField::SoldierName,
+        PurposeField::DiscordTag,
+        PurposeField::DiscordId,
+        PurposeField::SteamId,
+        PurposeField::EaId,
+        PurposeField::ServerName,
+    ];
+}
+
+impl PurposeAliases {
+    pub fn get(&self, field: PurposeField) -> &[String] {
+        match field {
+            PurposeField::SoldierName => &self.soldier_name,
+            PurposeField::DiscordTag => &self.discord_tag,
+            PurposeField::DiscordId => &self.discord_id,
+            PurposeField::SteamId => &self.steam_id,
+            PurposeField::EaId => &self.ea_id,
+            PurposeField::ServerName => &self.server_name,
+        }
+    }
+
+    fn get_mut(&mut self, field: PurposeField) -> &mut Vec<String> {
+        match field {
+            PurposeField::SoldierName => &mut self.soldier_name,
+            PurposeField::DiscordTag => &mut self.discord_tag,
+            PurposeField::DiscordId => &mut self.discord_id,
+            PurposeField::SteamId => &mut self.steam_id,
+            PurposeField::EaId => &mut self.ea_id,
+            PurposeField::ServerName => &mut self.server_name,
+        }
+    }
+}
+
+impl PurposeParser {
+    pub fn new() -> Self {
+        PurposeParser::default()
+    }
+
+    pub fn with_alias(mut self, field: PurposeField, alias: impl Into<String>) -> Self {
+        self.aliases.get_mut(field).push(alias.into());
+        self
+    }
+
+    fn field_for_key(&self, key: &str) -> Option<PurposeField> {
+        let key = normalize_key(key);
+        if key.is_empty() {
+            return None;
+        }
+
+        PurposeField::ALL
+            .iter()
+            .find(|field| self.aliases.get(**field).iter().any(|alias| normalize_key(alias) == key))
+            .copied()
+            .or_else(|| {
+                DEFAULT_ALIASES
+                    .iter()
+                    .find(|(_, aliases)| aliases.contains(&key.as_str()))
+                    .map(|(field, _)| *field)
+            })
+    }
+
+    pub fn parse(&self, purpose: &str) -> DonationPurpose {
+        let mut result = DonationPurpose::default();
+        let mut remainder: Vec<&str> = Vec::new();
+
+        for line in purpose.lines() {
+            let field = line
+                .find([':', '='])
+                .and_then(|index| {
+                    let value = line[index + 1..].trim();
+                    self.field_for_key(&line[..index])
+                        .filter(|_| !value.is_empty())
+                        .map(|field| (field, value))
+                });
+
+            match field {
+                Some((field, value)) => result.set(field, value),
+                None => remainder.push(line),
+            }
+        }
+
+        result.remainder = remainder.join("\n").trim().to_string();
+        result
+    }
+}
+
+impl DonationPurpose {
+    fn set(&mut self, field: PurposeField, value: &str) {
+        let value = Some(value.to_string());
+
+        match field {
+            PurposeField::SoldierName => self.soldier_name = value,
+            // Plain "Discord: <id>" is an ID, not a tag
+            PurposeField::DiscordTag if is_discord_id(value.as_deref().unwrap_or_default()) => {
+                self.discord_id = value
+            }
+            PurposeField::DiscordTag => self.discord_tag = value,
+            PurposeField::DiscordId => self.discord_id = value,
+            PurposeField::SteamId => self.steam_id = value,
+            PurposeField::EaId => self.ea_id = value,
+            PurposeField::ServerName => self.server_name = value,
+        }
+    }
+
+    pub fn get(&self, field: PurposeField) -> Option<&str> {
+        match field {
+            PurposeField::SoldierName => self.soldier_name.as_deref(),
+            PurposeField::DiscordTag => self.discord_tag.as_deref(),
+            PurposeField::DiscordId => self.discord_id.as_deref(),
+            PurposeField::SteamId => self.steam_id.as_deref(),
+            PurposeField::EaId => self.ea_id.as_deref(),
+            PurposeField::ServerName => self.server_name.as_deref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multiline_purpose() {
+        let purpose = PurposeParser::new().parse("soldiername: xfileFIN\nDiscord tag: xfileFIN#2811");
+
+        assert_eq!(purpose.soldier_name.as_deref(), Some("xfileFIN"));
+        assert_eq!(purpose.discord_tag.as_deref(), Some("xfileFIN#2811"));
+        assert_eq!(purpose.remainder, "");
+    }
+
+    #[test]
+    fn test_parse_keeps_unrecognised_lines() {
+        let purpose = PurposeParser::new().parse("Thanks for the server!\nDiscord: 123456789012345678\nServer = LSD #1\nps: keep it up");
+
+        assert_eq!(purpose.discord_id.as_deref(), Some("123456789012345678"));
+        assert_eq!(purpose.discord_tag, None);
+        assert_eq!(purpose.server_name.as_deref(), Some("LSD #1"));
+        assert_eq!(purpose.remainder, "Thanks for the server!\nps: keep it up");
+        assert_eq!(PurposeParser::new().parse("I neeed VIIIIP!!!").remainder, "I neeed VIIIIP!!!");
+    }
+
+    #[test]
+    fn test_parse_configured_alias() {
+        let parser = PurposeParser::new().with_alias(PurposeField::SoldierName, "Nimi");
+
+        assert_eq!(parser.parse("nimi: xfileFIN").soldier_name.as_deref(), Some("xfileFIN"));
+        assert_eq!(parser.parse("Soldier Name: xfileFIN").get(PurposeField::SoldierName), Some("xfileFIN"));
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+
+pub const DEFAULT_PAGE_SIZE: u32 = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    /// Let G-Portal decide (newest first)
+    Default,
+    Id,
+    Description,
+    Activity,
+    Time,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Let G-Portal decide (descending)
+    Default,
+    Ascending,
+    Descending,
+}
+
+impl SortColumn {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortColumn::Default => "-",
+            SortColumn::Id => "id",
+            SortColumn::Description => "description",
+            SortColumn::Activity => "activity",
+            SortColumn::Time => "time",
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Default => "-",
+            SortOrder::Ascending => "asc",
+            SortOrder::Descending => "desc",
+        }
+    }
+}
+
+impl fmt::Display for SortColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Query for the `profile/transactions` endpoint.
+///
+/// ```
+/// use api::{SortColumn, SortOrder, TransactionsQuery};
+///
+/// // Oldest first, for deterministic backfills
+/// let query = TransactionsQuery::new().sort_by(SortColumn::Time, SortOrder::Ascending);
+/// assert_eq!(query.path(), "0/25/time/asc");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionsQuery {
+    pub page: u32,
+    pub page_size: u32,
+    pub sort_column: SortColumn,
+    pub sort_order: SortOrder,
+}
+
+impl Default for TransactionsQuery {
+    fn default() -> Self {
+        TransactionsQuery::new()
+    }
+}
+
+impl TransactionsQuery {
+    pub fn new() -> Self {
+        TransactionsQuery {
+            page: 0,
+            page_size: DEFAULT_PAGE_SIZE,
+            sort_column: SortColumn::Default,
+            sort_order: SortOrder::Default,
+        }
+    }
+
+    pub fn page(mut self, page: u32) -> Self {
+        self.page = page;
+        self
+    }
+
+    pub fn page_size(mut self, page_size: u32) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    pub fn sort_by(mut self, sort_column: SortColumn, sort_order: SortOrder) -> Self {
+        self.sort_column = sort_column;
+        self.sort_order = sort_order;
+        self
+    }
+
+    /// Whether the oldest transactions come first with this query.
+    pub fn is_ascending(&self) -> bool {
+        self.sort_order == SortOrder::Ascending
+    }
+
+    pub fn path(&self) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            self.page, self.page_size, self.sort_column, self.sort_order
+        )
+    }
+}
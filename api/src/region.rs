@@ -0,0 +1,58 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// G-Portal storefront. Decides the URL paths used for the API and the currency
+/// the transaction amounts are expected to be in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    #[default]
+    Eur,
+    Us,
+}
+
+impl Region {
+    /// Path segment used by the storefront, e.g. `/eur/profile/...`
+    pub fn path(&self) -> &'static str {
+        match self {
+            Region::Eur => "eur",
+            Region::Us => "us",
+        }
+    }
+
+    /// ISO 4217 code of the currency used in the storefront
+    pub fn currency_code(&self) -> &'static str {
+        match self {
+            Region::Eur => "EUR",
+            Region::Us => "USD",
+        }
+    }
+
+    pub fn currency_symbol(&self) -> &'static str {
+        match self {
+            Region::Eur => "€",
+            Region::Us => "$",
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.path())
+    }
+}
+
+impl FromStr for Region {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "eur" | "eu" => Ok(Region::Eur),
+            "us" | "usd" => Ok(Region::Us),
+            _ => Err(Error::UnknownRegion(s.to_string())),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::{watch, Mutex};
+use tokio::time::sleep;
+
+use crate::{
+    config::{AccountConfig, Config},
+    gportal_auth::{self, GPortalAuth},
+    gportal_donations::GPortalDonations,
+    jwt::{self, JwksSource, TokenValidator},
+    notifier::{self, Notifier},
+    routing::Router,
+    state::StateConfig,
+    token_store::TokenStore,
+    totp::TotpConfig,
+};
+
+const DEFAULT_TOKEN_LEEWAY_SECS: i64 = 30;
+
+/// A G-Portal account polled with its own session, donation state and sinks
+pub struct Account {
+    pub name: String,
+    auth: Arc<Mutex<GPortalAuth>>,
+    donations: GPortalDonations,
+}
+
+impl Account {
+    /// Accounts of the `[[accounts]]` list, or the one of the `GPORTAL_*` environment variables when the list is empty.
+    /// Accounts without any notifier are left out.
+    pub fn from_config(config: &Config) -> Result<Vec<Account>, anyhow::Error> {
+        if config.accounts.is_empty() {
+            return Account::from_env(config).map(|account| account.into_iter().collect());
+        }
+
+        let mut accounts = Vec::new();
+        for account in &config.accounts {
+            match Account::from_account_config(account, config)? {
+                Some(account) => accounts.push(account),
+                None => warn!("Account {} has no notifiers, not polling it", account.name),
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /// The single account configured with `GPORTAL_USERNAME`, `GPORTAL_PASSWORD` and `TOTP_SECRET`
+    fn from_env(config: &Config) -> Result<Option<Account>, anyhow::Error> {
+        let username = dotenv::var("GPORTAL_USERNAME").map_err(|_| anyhow::anyhow!("GPORTAL_USERNAME is not set"))?;
+        let password = dotenv::var("GPORTAL_PASSWORD").map_err(|_| anyhow::anyhow!("GPORTAL_PASSWORD is not set"))?;
+        let totp_secret = dotenv::var("TOTP_SECRET").unwrap_or("".to_string());
+
+        let notifiers = notifier::build_notifiers(
+            &config.notifiers,
+            &config.discord,
+            &config.templates,
+            dotenv::var("DISCORD_DONATION_WEBHOOK").ok(),
+            &[],
+        )?;
+        if notifiers.is_empty() {
+            return Ok(None);
+        }
+
+        let auth = build_auth(username.clone(), password, &totp_secret, TokenStore::from_env()?)?;
+        let donations = build_donations(
+            config,
+            auth.clone(),
+            env_region()?,
+            config.pricing.clone(),
+            &config.state,
+            notifiers,
+            config.routes.clone(),
+        )?
+        .with_legacy_last_fetch();
+
+        Ok(Some(Account {
+            name: username,
+            auth,
+            donations,
+        }))
+    }
+
+    fn from_account_config(account: &AccountConfig, config: &Config) -> Result<Option<Account>, anyhow::Error> {
+        let notifiers = notifier::build_notifiers(
+            &config.notifiers,
+            &config.discord,
+            &config.templates,
+            dotenv::var("DISCORD_DONATION_WEBHOOK").ok(),
+            &account.notifiers,
+        )?;
+        if notifiers.is_empty() {
+            return Ok(None);
+        }
+
+        let password = dotenv::var(&account.password_env)
+            .map_err(|_| anyhow::anyhow!("{} is not set for account {}", account.password_env, account.name))?;
+        let totp_secret = match &account.totp_secret_env {
+            Some(name) => dotenv::var(name)
+                .map_err(|_| anyhow::anyhow!("{} is not set for account {}", name, account.name))?,
+            None => "".to_string(),
+        };
+        let token_path = account
+            .token_path
+            .clone()
+            .unwrap_or(format!("./data/{}/gportal_token.enc", account.name));
+
+        info!("Setting up account {}", account.name);
+        let auth = build_auth(
+            account.username.clone(),
+            password,
+            &totp_secret,
+            TokenStore::from_env_at(&token_path)?,
+        )?;
+        let state = account.state.clone().unwrap_or_else(|| config.state.for_account(&account.name));
+        let donations = build_donations(
+            config,
+            auth.clone(),
+            account.region.map_or_else(env_region, Ok)?,
+            account.pricing.clone().unwrap_or(config.pricing.clone()),
+            &state,
+            notifiers,
+            account.routes.clone().unwrap_or(config.routes.clone()),
+        )?;
+
+        Ok(Some(Account {
+            name: account.name.clone(),
+            auth,
+            donations,
+        }))
+    }
+
+    /// Polls every `interval` until `shutdown` changes, then revokes the session unless it's persisted
+    pub async fn run(mut self, interval: u64, backfill_since: Option<DateTime<Utc>>, mut shutdown: watch::Receiver<bool>) {
+        let login = self.auth.lock().await.access_token().await;
+        if let Err(err) = login {
+            // Polling can't work without the scope, no use retrying
+            if err.downcast_ref::<jwt::MissingScope>().is_some() {
+                error!("[{}] {:#}", self.name, err);
+                return;
+            }
+            error!("[{}] Failed to log in to G-Portal: {:#}", self.name, err);
+        } else if let Some(identity) = self.auth.lock().await.identity() {
+            debug!("[{}] Granted G-Portal scopes: {}", self.name, identity.scopes.join(" "));
+        }
+
+        let refresh_task = gportal_auth::spawn_refresh_task(self.auth.clone());
+
+        if let Some(since) = backfill_since {
+            if let Err(err) = self.donations.backfill(since).await {
+                error!("[{}] Error while backfilling donations: {}", self.name, err);
+            }
+        }
+
+        // Shutdown is only checked between polls, cancelling one could drop notifications mid-flight
+        while !*shutdown.borrow() {
+            if let Err(err) = self.donations.check_new_donations().await {
+                error!("[{}] Error while polling new donations: {}", self.name, err);
+            }
+
+            info!(
+                "[{}] Polling for new donations done, next poll at {}",
+                self.name,
+                crate::get_time_after_duration(interval)
+            );
+            tokio::select! {
+                _ = sleep(Duration::from_millis(interval)) => (),
+                Ok(()) = shutdown.changed() => (),
+            }
+        }
+        refresh_task.abort();
+
+        let mut auth = self.auth.lock().await;
+        if auth.is_persisted() {
+            info!("[{}] Keeping the G-Portal session for the next start", self.name);
+        } else if let Err(err) = auth.logout().await {
+            warn!("[{}] Failed to log out of G-Portal: {:#}", self.name, err);
+        }
+    }
+}
+
+fn build_auth(
+    username: String,
+    password: String,
+    totp_secret: &str,
+    token_store: Option<TokenStore>,
+) -> Result<Arc<Mutex<GPortalAuth>>, anyhow::Error> {
+    let auth = if totp_secret.is_empty() {
+        GPortalAuth::new(username, password)
+    } else {
+        GPortalAuth::new_with_totp(username, password, TotpConfig::from_env(totp_secret)?)
+    };
+
+    let token_leeway: i64 = dotenv::var("GPORTAL_TOKEN_LEEWAY")
+        .map(|var| var.parse::<i64>())
+        .unwrap_or(Ok(DEFAULT_TOKEN_LEEWAY_SECS))?;
+    let jwks_source = match dotenv::var("GPORTAL_JWKS_PATH") {
+        Ok(path) => JwksSource::File(path),
+        Err(_) => JwksSource::Discovery,
+    };
+    let auth = auth
+        .with_leeway(chrono::Duration::seconds(token_leeway))
+        .with_validator(TokenValidator::new(jwks_source));
+    let auth = match token_store {
+        Some(token_store) => auth.with_token_store(token_store),
+        None => auth,
+    };
+
+    Ok(Arc::new(Mutex::new(auth)))
+}
+
+fn build_donations(
+    config: &Config,
+    auth: Arc<Mutex<GPortalAuth>>,
+    region: api::Region,
+    pricing: api::PricingPolicy,
+    state: &StateConfig,
+    notifiers: Vec<Box<dyn Notifier>>,
+    routes: Vec<crate::routing::Route>,
+) -> Result<GPortalDonations, anyhow::Error> {
+    info!("Using G-Portal region: {} ({})", region, region.currency_code());
+    let api_client = match dotenv::var("GPORTAL_URL") {
+        Ok(url) => api::GPortalClient::new().with_base_url(url),
+        Err(_) => api::GPortalClient::new(),
+    }
+    .with_region(region);
+
+    Ok(GPortalDonations::new(auth, api_client, pricing, config.purpose.clone(), state.open()?, notifiers)
+        .with_router(Router::new(routes))
+        .with_first_run(config.first_run.clone())
+        .with_retry_policy(config.retry.clone()))
+}
+
+fn env_region() -> Result<api::Region, anyhow::Error> {
+    Ok(dotenv::var("GPORTAL_REGION")
+        .map(|var| var.parse::<api::Region>())
+        .unwrap_or(Ok(api::Region::default()))?)
+}
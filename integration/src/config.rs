@@ -0,0 +1,189 @@
+use std::{fs, path::Path};
+
+use api::{PricingPolicy, PurposeParser, Region};
+use serde::Deserialize;
+
+use crate::{
+    discord::EmbedTemplate,
+    gportal_donations::FirstRunPolicy,
+    notifier::{NotifierConfig, Templates},
+    outbox::RetryPolicy,
+    routing::Route,
+    state::StateConfig,
+};
+
+const CONFIG_PATH: &str = r#"./data/config.toml"#;
+
+/// Optional TOML configuration for everything that doesn't fit in an environment variable.
+/// Every section falls back to its default when missing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub pricing: PricingPolicy,
+    pub purpose: PurposeParser,
+    pub state: StateConfig,
+    pub first_run: FirstRunPolicy,
+    pub retry: RetryPolicy,
+    pub discord: EmbedTemplate,
+    pub notifiers: Vec<NotifierConfig>,
+    pub templates: Templates,
+    pub routes: Vec<Route>,
+    /// Without accounts the one configured with the `GPORTAL_*` environment variables is polled
+    pub accounts: Vec<AccountConfig>,
+}
+
+/// Entry of the `[[accounts]]` list, a G-Portal account polled with its own session, state and sinks
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AccountConfig {
+    /// Unique name, used in the logs and the default data paths
+    pub name: String,
+    pub username: String,
+    /// Environment variable holding the password, so it stays out of the config file
+    pub password_env: String,
+    /// Environment variable holding the TOTP secret, if the account uses two-factor authentication
+    pub totp_secret_env: Option<String>,
+    /// Defaults to `GPORTAL_REGION`
+    pub region: Option<Region>,
+    /// Defaults to the top-level `[pricing]`
+    pub pricing: Option<PricingPolicy>,
+    /// Defaults to the `[state]` backend with the data in `./data/<name>/`
+    pub state: Option<StateConfig>,
+    /// Defaults to `./data/<name>/gportal_token.enc`
+    pub token_path: Option<String>,
+    /// Names of the `[[notifiers]]` the donations are sent to, all of them when empty
+    pub notifiers: Vec<String>,
+    /// Defaults to the top-level `[[routes]]`
+    pub routes: Option<Vec<Route>>,
+}
+
+impl Config {
+    pub fn load() -> Result<Config, anyhow::Error> {
+        let path = dotenv::var("CONFIG_PATH").unwrap_or(CONFIG_PATH.to_string());
+
+        if !Path::new(&path).exists() {
+            info!("No config file found at {}, using defaults", path);
+            return Ok(Config::default());
+        }
+
+        info!("Loading config from {}", path);
+        Config::parse(&fs::read_to_string(&path)?)
+    }
+
+    pub fn parse(content: &str) -> Result<Config, anyhow::Error> {
+        let config: Config = toml::from_str(content)?;
+
+        for (i, account) in config.accounts.iter().enumerate() {
+            if account.name.is_empty() || account.username.is_empty() || account.password_env.is_empty() {
+                return Err(anyhow::anyhow!("Account {} needs a name, username and password_env", i + 1));
+            }
+            if config.accounts[..i].iter().any(|other| other.name == account.name) {
+                return Err(anyhow::anyhow!("Account {} is configured twice", account.name));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// State of the named account, or of the single account when `account` is `None` and no `[[accounts]]` are configured
+    pub fn state_for(&self, account: Option<&str>) -> Result<StateConfig, anyhow::Error> {
+        let name = match account {
+            Some(name) => name,
+            None if self.accounts.is_empty() => return Ok(self.state.clone()),
+            None => {
+                let names: Vec<&str> = self.accounts.iter().map(|account| account.name.as_str()).collect();
+                return Err(anyhow::anyhow!(
+                    "Accounts are configured in [[accounts]], pick one with --account: {}",
+                    names.join(", ")
+                ));
+            }
+        };
+
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No account named {} in the config", name))?;
+
+        Ok(account.state.clone().unwrap_or_else(|| self.state.for_account(&account.name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pricing_config() {
+        let config = Config::parse(
+            r#"
+            [pricing]
+            minimum_amount = 2
+            tiers = [{ from = 0, amount = 5, days = 30 }]
+
+            [pricing.currencies.USD]
+            packages = [{ amount = 10, days = 60 }]
+
+            [purpose.aliases]
+            soldier_name = ["nimi"]
+
+            [first_run]
+            mode = "last"
+            count = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.pricing.default.tiers.len(), 1);
+        assert!(config.pricing.currencies.contains_key("USD"));
+        assert_eq!(config.purpose.parse("Nimi: xfileFIN").soldier_name.as_deref(), Some("xfileFIN"));
+        assert_eq!(config.first_run, FirstRunPolicy::Last { count: 3 });
+        assert_eq!(Config::parse("").unwrap().pricing, PricingPolicy::default());
+        assert_eq!(Config::parse("").unwrap().first_run, FirstRunPolicy::Skip);
+    }
+
+    #[test]
+    fn test_parse_accounts() {
+        let config = Config::parse(
+            r#"
+            [state]
+            backend = "sqlite"
+
+            [[accounts]]
+            name = "bf4"
+            username = "bf4@example.com"
+            password_env = "BF4_GPORTAL_PASSWORD"
+            notifiers = ["bf4-discord"]
+
+            [[accounts]]
+            name = "bfv"
+            username = "bfv@example.com"
+            password_env = "BFV_GPORTAL_PASSWORD"
+            region = "us"
+            state = { backend = "json", path = "./bfv.json" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.accounts.len(), 2);
+        assert_eq!(config.accounts[1].region, Some(Region::Us));
+        assert_eq!(config.state_for(Some("bf4")).unwrap().path.as_deref(), Some("./data/bf4/donations.sqlite"));
+        assert_eq!(config.state_for(Some("bfv")).unwrap().path.as_deref(), Some("./bfv.json"));
+        assert!(config.state_for(Some("bf1")).is_err());
+        assert!(config.state_for(None).is_err());
+
+        let duplicate = r#"
+            [[accounts]]
+            name = "bf4"
+            username = "a"
+            password_env = "A"
+
+            [[accounts]]
+            name = "bf4"
+            username = "b"
+            password_env = "B"
+            "#;
+        assert!(Config::parse(duplicate).is_err());
+        assert!(Config::parse("[[accounts]]\nname = \"bf4\"").is_err());
+    }
+}
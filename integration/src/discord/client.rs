@@ -0,0 +1,359 @@
+use std::time::{Duration, Instant};
+
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+/// Discord accepts at most 10 embeds per message
+pub const MAX_EMBEDS_PER_MESSAGE: usize = 10;
+/// Discord rejects messages whose embeds have more characters than this in total
+pub const MAX_EMBED_CHARS_PER_MESSAGE: usize = 6000;
+/// Rate limited responses retried before a message is given up on
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmbedAuthor {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmbedFooter {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Embed {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// ISO 8601 timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<EmbedAuthor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footer: Option<EmbedFooter>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<EmbedField>,
+}
+
+impl Embed {
+    /// Characters that count towards Discord's limit per message
+    pub fn text_length(&self) -> usize {
+        let texts = [
+            self.title.as_deref(),
+            self.description.as_deref(),
+            self.author.as_ref().map(|author| author.name.as_str()),
+            self.footer.as_ref().map(|footer| footer.text.as_str()),
+        ];
+        let fields = self.fields.iter().flat_map(|field| [field.name.as_str(), field.value.as_str()]);
+
+        texts.iter().flatten().copied().chain(fields).map(|text| text.chars().count()).sum()
+    }
+
+    pub fn field(&mut self, name: &str, value: &str, inline: bool) -> &mut Self {
+        self.fields.push(EmbedField {
+            name: name.to_string(),
+            value: value.to_string(),
+            inline,
+        });
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Message<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar_url: Option<&'a str>,
+    embeds: Vec<&'a Embed>,
+}
+
+/// Body of a 429 response
+#[derive(Debug, Deserialize)]
+struct RateLimited {
+    retry_after: f64,
+    #[serde(default)]
+    global: bool,
+}
+
+/// Webhook client keeping track of Discord's rate limits.
+///
+/// Embeds are queued and sent in as few messages as possible when flushed. Requests wait for the
+/// rate limit bucket to reset when it runs out, and rate limited requests are retried after `Retry-After`.
+pub struct DiscordWebhookClient {
+    client: reqwest::Client,
+    webhook_url: String,
+    username: Option<String>,
+    avatar_url: Option<String>,
+    queue: Vec<(String, Embed)>,
+    /// Set when the bucket has no requests left, until it resets
+    blocked_until: Option<Instant>,
+}
+
+impl DiscordWebhookClient {
+    pub fn new(webhook_url: &str) -> Self {
+        DiscordWebhookClient {
+            client: reqwest::Client::new(),
+            webhook_url: webhook_url.to_string(),
+            username: None,
+            avatar_url: None,
+            queue: Vec::new(),
+            blocked_until: None,
+        }
+    }
+
+    pub fn with_username(mut self, username: &str) -> Self {
+        self.username = Some(username.to_string());
+        self
+    }
+
+    pub fn with_avatar_url(mut self, avatar_url: &str) -> Self {
+        self.avatar_url = Some(avatar_url.to_string());
+        self
+    }
+
+    /// Queues an embed to be sent on the next flush, `id` identifies it in the results
+    pub fn queue(&mut self, id: &str, embed: Embed) {
+        self.queue.push((id.to_string(), embed));
+    }
+
+    /// Sends every queued embed, up to 10 and 6000 characters per message, and returns the result of each in queue order
+    pub async fn flush(&mut self) -> Vec<(String, Result<(), anyhow::Error>)> {
+        let queue = std::mem::take(&mut self.queue);
+        let mut results = Vec::with_capacity(queue.len());
+
+        for batch in batches(&queue) {
+            let message = Message {
+                username: self.username.as_deref(),
+                avatar_url: self.avatar_url.as_deref(),
+                embeds: batch.iter().map(|(_, embed)| embed).collect(),
+            };
+            let body = serde_json::to_string(&message);
+
+            let result = match body {
+                Ok(body) => self.send(body).await,
+                Err(err) => Err(err.into()),
+            };
+
+            match result {
+                Ok(()) => results.extend(batch.iter().map(|(id, _)| (id.clone(), Ok(())))),
+                Err(err) => {
+                    let err = err.to_string();
+                    results.extend(batch.iter().map(|(id, _)| (id.clone(), Err(anyhow::anyhow!("{}", err)))));
+                }
+            }
+        }
+
+        results
+    }
+
+    async fn send(&mut self, body: String) -> Result<(), anyhow::Error> {
+        let mut retries = 0;
+
+        loop {
+            if let Some(blocked_until) = self.blocked_until.take() {
+                let now = Instant::now();
+                if blocked_until > now {
+                    debug!("Discord rate limit bucket is empty, waiting {:?}", blocked_until - now);
+                    sleep(blocked_until - now).await;
+                }
+            }
+
+            let res = self
+                .client
+                .post(&self.webhook_url)
+                .header("Content-Type", "application/json")
+                .body(body.clone())
+                .send()
+                .await
+                // The webhook URL contains its token, keep it out of the logs and the outbox
+                .map_err(reqwest::Error::without_url)?;
+
+            let status = res.status();
+            self.update_bucket(res.headers());
+
+            if status.is_success() {
+                return Ok(());
+            }
+
+            let retry_after = get_retry_after(res.headers());
+            let text = res.text().await.map_err(reqwest::Error::without_url)?;
+
+            if status != StatusCode::TOO_MANY_REQUESTS {
+                return Err(anyhow::anyhow!("Discord webhook failed with {}: {}", status, text));
+            }
+
+            if retries >= MAX_RATE_LIMIT_RETRIES {
+                return Err(anyhow::anyhow!("Discord webhook is still rate limited after {} retries", retries));
+            }
+            retries += 1;
+
+            let (retry_after, global) = match serde_json::from_str::<RateLimited>(&text) {
+                Ok(limited) => (Duration::from_secs_f64(limited.retry_after.max(0.0)), limited.global),
+                Err(_) => (retry_after.unwrap_or(Duration::from_secs(1)), false),
+            };
+            warn!(
+                "Discord webhook rate limited{}, retrying in {:?}",
+                if global { " globally" } else { "" },
+                retry_after
+            );
+            sleep(retry_after).await;
+        }
+    }
+
+    fn update_bucket(&mut self, headers: &HeaderMap) {
+        let remaining = get_header::<u32>(headers, "X-RateLimit-Remaining");
+        let reset_after = get_header::<f64>(headers, "X-RateLimit-Reset-After");
+
+        if let (Some(0), Some(reset_after)) = (remaining, reset_after) {
+            self.blocked_until = Some(Instant::now() + Duration::from_secs_f64(reset_after.max(0.0)));
+        }
+    }
+}
+
+/// Splits the queue into messages within Discord's limits, an embed over the size limit is sent on its own
+fn batches(queue: &[(String, Embed)]) -> Vec<&[(String, Embed)]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut chars = 0;
+
+    for (i, (_, embed)) in queue.iter().enumerate() {
+        let length = embed.text_length();
+        if i > start && (i - start >= MAX_EMBEDS_PER_MESSAGE || chars + length > MAX_EMBED_CHARS_PER_MESSAGE) {
+            batches.push(&queue[start..i]);
+            start = i;
+            chars = 0;
+        }
+        chars += length;
+    }
+    if start < queue.len() {
+        batches.push(&queue[start..]);
+    }
+
+    batches
+}
+
+fn get_header<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+fn get_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    get_header::<f64>(headers, "Retry-After").map(|secs| Duration::from_secs_f64(secs.max(0.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn embed(title: &str) -> Embed {
+        Embed {
+            title: Some(title.to_string()),
+            ..Embed::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_embeds() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let mut client = DiscordWebhookClient::new(&server.uri()).with_username("G-Portal");
+        for i in 0..12 {
+            client.queue(&i.to_string(), embed(&i.to_string()));
+        }
+
+        let results = client.flush().await;
+        assert_eq!(results.len(), 12);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(results[11].0, "11");
+
+        let requests = server.received_requests().await.unwrap();
+        let first: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(first["embeds"].as_array().unwrap().len(), 10);
+        assert_eq!(first["username"], "G-Portal");
+    }
+
+    #[test]
+    fn test_batches_by_size() {
+        let long = Embed {
+            description: Some("a".repeat(2500)),
+            ..Embed::default()
+        };
+        let queue: Vec<(String, Embed)> = (0..5).map(|i| (i.to_string(), long.clone())).collect();
+
+        let sizes: Vec<usize> = batches(&queue).iter().map(|batch| batch.len()).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+
+        let huge = Embed {
+            description: Some("a".repeat(7000)),
+            ..Embed::default()
+        };
+        let queue = vec![("0".to_string(), embed("a")), ("1".to_string(), huge), ("2".to_string(), embed("b"))];
+        let sizes: Vec<usize> = batches(&queue).iter().map(|batch| batch.len()).collect();
+        assert_eq!(sizes, vec![1, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limited() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .set_body_string(r#"{ "message": "You are being rate limited.", "retry_after": 0.05, "global": false }"#),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut client = DiscordWebhookClient::new(&server.uri());
+        client.queue("14500000", embed("New donation received"));
+
+        let results = client.flush().await;
+        assert!(results[0].1.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("Invalid Form Body"))
+            .mount(&server)
+            .await;
+
+        let mut client = DiscordWebhookClient::new(&server.uri());
+        client.queue("14500000", embed("a"));
+        client.queue("14500001", embed("b"));
+
+        let results = client.flush().await;
+        assert_eq!(results.len(), 2);
+        assert!(results[1].1.as_ref().unwrap_err().to_string().contains("400"));
+    }
+}
@@ -0,0 +1,213 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::{Embed, EmbedAuthor, EmbedFooter};
+use crate::notifier::{render_template, DonationContext};
+
+const DEFAULT_USERNAME: &str = r#"G-Portal"#;
+const DEFAULT_AVATAR_URL: &str = r#"https://cdn.discordapp.com/attachments/1036028334355795968/1036287507651907674/unknown.png"#;
+const DEFAULT_FOOTER: &str = r#"Webhook by xfileFIN"#;
+const DEFAULT_COLOR: u32 = 15844367;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FieldTemplate {
+    pub name: String,
+    pub value: String,
+    #[serde(default = "default_inline")]
+    pub inline: bool,
+}
+
+fn default_inline() -> bool {
+    true
+}
+
+/// Colour used from the given amount upwards
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ColorTier {
+    pub from: Decimal,
+    pub color: u32,
+}
+
+/// `[discord]` section of the config. Texts are Handlebars templates rendered from a [`DonationContext`],
+/// anything left out comes from the built-in texts of the `locale`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EmbedTemplate {
+    pub username: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Built-in texts to use, `en` or `fi`
+    pub locale: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub author_url: Option<String>,
+    pub footer: Option<String>,
+    pub color: Option<u32>,
+    /// The tier with the highest `from` not above the amount decides the colour
+    pub colors: Vec<ColorTier>,
+    /// Fields rendering to an empty value are left out
+    pub fields: Option<Vec<FieldTemplate>>,
+}
+
+/// Built-in texts of a locale
+struct Locale {
+    title: &'static str,
+    amount: &'static str,
+    days: &'static str,
+    end_date: &'static str,
+    soldier_name: &'static str,
+    discord_id: &'static str,
+    server: &'static str,
+}
+
+const EN: Locale = Locale {
+    title: "New donation received",
+    amount: "Amount",
+    days: "days",
+    end_date: "End date",
+    soldier_name: "Soldier name",
+    discord_id: "Discord ID",
+    server: "Server",
+};
+
+const FI: Locale = Locale {
+    title: "Uusi lahjoitus vastaanotettu",
+    amount: "Summa",
+    days: "päivää",
+    end_date: "Päättyy",
+    soldier_name: "Sotilasnimi",
+    discord_id: "Discord ID",
+    server: "Palvelin",
+};
+
+fn locale(name: Option<&str>) -> &'static Locale {
+    match name.map(|name| name.to_lowercase()).as_deref() {
+        None | Some("en") => &EN,
+        Some("fi") => &FI,
+        Some(other) => {
+            warn!("Unknown embed locale {}, using en", other);
+            &EN
+        }
+    }
+}
+
+impl EmbedTemplate {
+    pub fn username(&self) -> &str {
+        self.username.as_deref().unwrap_or(DEFAULT_USERNAME)
+    }
+
+    pub fn avatar_url(&self) -> &str {
+        self.avatar_url.as_deref().unwrap_or(DEFAULT_AVATAR_URL)
+    }
+
+    fn default_fields(locale: &Locale) -> Vec<FieldTemplate> {
+        let field = |name: &str, value: &str| FieldTemplate {
+            name: name.to_string(),
+            value: value.to_string(),
+            inline: true,
+        };
+
+        vec![
+            field(locale.amount, &format!("{{{{amount}}}} ({{{{days}}}} {})", locale.days)),
+            field(locale.end_date, "<t:{{end_timestamp}}:R>"),
+            field(locale.soldier_name, "{{soldier_name}}"),
+            field("Discord", "{{discord_tag}}"),
+            field(locale.discord_id, "{{discord_id}}"),
+            field("Steam ID", "{{steam_id}}"),
+            field("EA ID", "{{ea_id}}"),
+            field(locale.server, "{{server_name}}"),
+        ]
+    }
+
+    fn color(&self, context: &DonationContext) -> u32 {
+        self.colors
+            .iter()
+            .filter(|tier| tier.from <= context.amount_value)
+            .max_by(|a, b| a.from.cmp(&b.from))
+            .map(|tier| tier.color)
+            .or(self.color)
+            .unwrap_or(DEFAULT_COLOR)
+    }
+
+    pub fn render(&self, context: &DonationContext) -> Result<Embed, anyhow::Error> {
+        let locale = locale(self.locale.as_deref());
+
+        let render = |template: &str| render_template(template, context);
+        let render_optional = |template: &str| -> Result<Option<String>, anyhow::Error> {
+            let rendered = render(template)?;
+            Ok(Some(rendered).filter(|value| !value.is_empty()))
+        };
+
+        let author_url = render_optional(self.author_url.as_deref().unwrap_or("{{vip_management_url}}"))?;
+        let mut embed = Embed {
+            title: render_optional(self.title.as_deref().unwrap_or(locale.title))?,
+            description: render_optional(self.description.as_deref().unwrap_or("{{purpose}}"))?,
+            timestamp: Some(context.time.clone()),
+            color: Some(self.color(context)),
+            author: render_optional(self.author.as_deref().unwrap_or("{{donor}}"))?
+                .map(|name| EmbedAuthor { name, url: author_url }),
+            footer: render_optional(self.footer.as_deref().unwrap_or(DEFAULT_FOOTER))?.map(|text| EmbedFooter { text }),
+            fields: Vec::new(),
+        };
+
+        let fields = match &self.fields {
+            Some(fields) => fields.clone(),
+            None => EmbedTemplate::default_fields(locale),
+        };
+        for field in fields {
+            let value = render(&field.value)?;
+            if !value.is_empty() {
+                embed.field(&render(&field.name)?, &value, field.inline);
+            }
+        }
+
+        Ok(embed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::tests::donation_context;
+
+    fn context(amount: &str) -> DonationContext {
+        donation_context("Donation from xfileFIN@xfileFIN.com - Purpose: soldiername: xfileFIN", amount)
+    }
+
+    #[test]
+    fn test_default_embed() {
+        let embed = EmbedTemplate::default().render(&context("10.00 €")).unwrap();
+
+        assert_eq!(embed.title.as_deref(), Some("New donation received"));
+        assert_eq!(embed.author.unwrap().name, "x***@x***.com");
+        assert_eq!(embed.color, Some(DEFAULT_COLOR));
+        assert_eq!(embed.timestamp.as_deref(), Some("2022-10-01T19:50:01.000Z"));
+        assert_eq!(embed.fields[0].value, "10.00 € (90 days)");
+        assert_eq!(embed.fields[2].name, "Soldier name");
+        assert_eq!(embed.fields[2].value, "xfileFIN");
+        // Empty purpose fields are left out
+        assert_eq!(embed.fields.len(), 3);
+    }
+
+    #[test]
+    fn test_custom_embed() {
+        let template: EmbedTemplate = toml::from_str(
+            r#"
+            locale = "fi"
+            description = "{{soldier_name}} lahjoitti {{amount}}"
+            color = 1
+            colors = [{ from = 5, color = 2 }, { from = 10, color = 3 }]
+            "#,
+        )
+        .unwrap();
+
+        let embed = template.render(&context("5.00 €")).unwrap();
+        assert_eq!(embed.title.as_deref(), Some("Uusi lahjoitus vastaanotettu"));
+        assert_eq!(embed.description.as_deref(), Some("xfileFIN lahjoitti 5.00 €"));
+        assert_eq!(embed.fields[0].name, "Summa");
+        assert_eq!(embed.color, Some(2));
+
+        assert_eq!(template.render(&context("25.00 €")).unwrap().color, Some(3));
+        assert_eq!(template.render(&context("1.00 €")).unwrap().color, Some(1));
+    }
+}
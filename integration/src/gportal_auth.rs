@@ -1,127 +1,379 @@
+use std::sync::Arc;
+
 use chrono::{DateTime, Utc, Duration};
-use serde_json::json;
-use totp_rs::{Algorithm, Secret, TOTP};
+use tokio::{sync::Mutex, task::JoinHandle};
 
-use crate::openid::{self, Token};
+use crate::jwt::{Identity, TokenValidator};
+use crate::openid::{AuthError, OidcClient, Token};
+use crate::token_store::{StoredToken, TokenStore};
+use crate::totp::TotpConfig;
 
 pub struct GPortalAuth {
     username: String,
     password: String,
-    totp_secret: Option<String>,
+    totp: Option<TotpConfig>,
     token: Option<Token>,
     fetch_time: Option<DateTime<Utc>>,
+    token_store: Option<TokenStore>,
+    /// Tokens are treated as expired this long before they actually expire
+    leeway: Duration,
+    issuer: String,
+    oidc: Option<OidcClient>,
+    /// Password logins are paused until then after Keycloak refused the credentials, so the account doesn't get locked
+    login_blocked_until: Option<DateTime<Utc>>,
+    /// Auth server time minus local time, measured from the `Date` header when a TOTP code is refused
+    clock_offset: Duration,
+    /// Verifies new tokens when set
+    validator: Option<TokenValidator>,
+    identity: Option<Identity>,
 }
 
-const API_URL: &str = r#"https://auth.g-portal.com/auth/realms/master/protocol/openid-connect/token"#;
+const ISSUER: &str = r#"https://auth.g-portal.com/auth/realms/master"#;
 const CLIENT_ID: &str = r#"website"#;
 const SCOPE: &str = r#"openid email profile gportal"#;
+const DEFAULT_LEEWAY_SECS: i64 = 30;
+/// How often the background refresh checks again when there is nothing to refresh
+const IDLE_REFRESH_INTERVAL_SECS: i64 = 60;
+/// Pause after refused credentials, Keycloak's brute force detection locks the account after repeated failures
+const LOGIN_BACKOFF_SECS: i64 = 900;
+/// Date headers only have second precision and include the latency, smaller differences are noise
+const MIN_CLOCK_DRIFT_SECS: i64 = 2;
+const WARN_CLOCK_DRIFT_SECS: i64 = 10;
+/// Keeps tokens shorter lived than the leeway from being refreshed in a tight loop
+const MIN_REFRESH_INTERVAL_SECS: i64 = 5;
 
 impl GPortalAuth {
     pub fn new(username: String, password: String) -> Self {
         GPortalAuth {
             username,
             password,
-            totp_secret: None,
+            totp: None,
             token: None,
             fetch_time: None,
+            token_store: None,
+            leeway: Duration::seconds(DEFAULT_LEEWAY_SECS),
+            issuer: ISSUER.to_string(),
+            oidc: None,
+            login_blocked_until: None,
+            clock_offset: Duration::zero(),
+            validator: None,
+            identity: None,
         }
     }
 
-    pub fn new_with_totp(username: String, password: String, totp_secret: String) -> Self {
+    pub fn new_with_totp(username: String, password: String, totp: TotpConfig) -> Self {
         GPortalAuth {
             username,
             password,
-            totp_secret: Some(totp_secret),
+            totp: Some(totp),
             token: None,
             fetch_time: None,
+            token_store: None,
+            leeway: Duration::seconds(DEFAULT_LEEWAY_SECS),
+            issuer: ISSUER.to_string(),
+            oidc: None,
+            login_blocked_until: None,
+            clock_offset: Duration::zero(),
+            validator: None,
+            identity: None,
         }
     }
 
-    pub fn get_totp_code(totp_secret: &str) -> Result<String, anyhow::Error> {
-        let secret = Secret::Encoded(totp_secret.to_string()).to_bytes();
-        if secret.is_err() {
-            debug!("Trying to get TOTP code with invalid secret");
-            return Err(anyhow::anyhow!("TOTP Secret was invalid"));
-        }
-    
-        let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret.unwrap());
-    
-        if totp.is_err() {
-            debug!("Failed to initialize TOTP");
-            return Err(anyhow::anyhow!("Failed to create new TOTP"));
+    /// Starts with a session that was just fetched
+    #[cfg(test)]
+    pub fn with_token(mut self, token: Token) -> Self {
+        self.token = Some(token);
+        self.fetch_time = Some(Utc::now());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = issuer.to_string();
+        self
+    }
+
+    /// Verifies the signature and scopes of every new token with `validator`
+    pub fn with_validator(mut self, validator: TokenValidator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Refreshes tokens `leeway` before they expire, so a token doesn't run out between fetching and using it
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Persists the token in `token_store` and continues the stored session if its refresh token is still valid
+    pub fn with_token_store(mut self, token_store: TokenStore) -> Self {
+        match token_store.load(&self.username) {
+            Ok(Some(stored)) => {
+                self.token = Some(stored.token);
+                self.fetch_time = Some(stored.fetch_time);
+
+                if self.is_refresh_token_expired() {
+                    debug!("Stored G-Portal session has expired");
+                    self.token = None;
+                    self.fetch_time = None;
+                } else {
+                    info!("Continuing the stored G-Portal session of {}", self.username);
+                }
+            }
+            Ok(None) => (),
+            Err(err) => warn!("Ignoring the stored G-Portal token: {}", err),
         }
-    
-        debug!("Generating new TOTP code");
-        Ok(totp.unwrap().generate_current()?)
+
+        self.token_store = Some(token_store);
+        self
     }
 
+    #[cfg(test)]
+    pub fn get_totp_code(totp_secret: &str) -> Result<String, anyhow::Error> {
+        Ok(TotpConfig::parse(totp_secret)?.code_at(Utc::now()))
+    }
+
+    #[cfg(test)]
     pub async fn token_by_password(
         username: &str,
         password: &str,
         totp_code: &str,
-    ) -> Result<Token, reqwest::Error> {
-        let payload = json!({
-            "grant_type":"password",
-            "client_id":CLIENT_ID,
-            "scope":SCOPE,
-            "username":username,
-            "password":password,
-            "rememberMe":"on",
-            "totp":totp_code,
-        });
-
-        openid::get_token(&API_URL, payload)
+    ) -> Result<Token, AuthError> {
+        OidcClient::discover(ISSUER, CLIENT_ID)
+            .await?
+            .password_grant(username, password, totp_code, SCOPE)
             .await
     }
 
+    #[cfg(test)]
     pub async fn token_by_refreshtoken(
         refresh_token: &str,
-    ) -> Result<Token, reqwest::Error> {
-        let payload = json!({
-            "grant_type":"refresh_token",
-            "client_id":CLIENT_ID,
-            "refresh_token":refresh_token,
-        });
-
-        openid::get_token(&API_URL, payload)
-            .await
+    ) -> Result<Token, AuthError> {
+        OidcClient::discover(ISSUER, CLIENT_ID).await?.refresh_grant(refresh_token).await
+    }
+
+    /// OpenID client of the realm, discovered on first use
+    async fn oidc(&mut self) -> Result<&OidcClient, AuthError> {
+        if self.oidc.is_none() {
+            let oidc = OidcClient::discover(&self.issuer, CLIENT_ID).await?;
+            if let Some(validator) = self.validator.as_mut() {
+                validator.set_provider(oidc.metadata(), oidc.client_id());
+            }
+            self.oidc = Some(oidc);
+        }
+
+        Ok(self.oidc.as_ref().unwrap())
+    }
+
+    async fn password_login(&mut self, time: DateTime<Utc>) -> Result<Token, AuthError> {
+        let totp_code = self.totp.as_ref().map(|totp| totp.code_at(time)).unwrap_or_default();
+        let (username, password) = (self.username.clone(), self.password.clone());
+        self.oidc().await?.password_grant(&username, &password, &totp_code, SCOPE).await
+    }
+
+    /// Current time on the auth server as far as we know
+    fn server_now(&self) -> DateTime<Utc> {
+        Utc::now() + self.clock_offset
+    }
+
+    /// Adjusts the clock offset from the `Date` of an auth server response, returns whether it changed noticeably
+    fn update_clock_offset(&mut self, server_time: DateTime<Utc>) -> bool {
+        let offset = server_time - Utc::now();
+        if (offset - self.clock_offset).num_seconds().abs() < MIN_CLOCK_DRIFT_SECS {
+            return false;
+        }
+
+        if offset.num_seconds().abs() >= WARN_CLOCK_DRIFT_SECS {
+            warn!(
+                "Local clock is {} seconds off from G-Portal's, TOTP codes are generated with G-Portal's time. Consider syncing the system clock.",
+                offset.num_seconds()
+            );
+        }
+        self.clock_offset = offset;
+
+        true
     }
 
+    /// Valid access token, trying the access token, the refresh token and the password in that order
     pub async fn access_token(&mut self) -> Result<String, anyhow::Error> {
         // Access token is valid
-        if self.token.is_some() && !self.is_token_expired() {
+        if let Some(token) = self.token.as_ref().filter(|_| !self.is_token_expired()) {
             debug!("Access token is valid so using that.");
 
-            return Ok(self.token.as_ref().unwrap().access_token.clone());
+            return Ok(token.access_token.clone());
         }
 
         // Access token is invalid but refresh token is valid
         if self.token.is_some() && !self.is_refresh_token_expired() {
             debug!("Access token is invalid but refresh token is valid so using that to fetch a new token.");
 
-            let new_token = GPortalAuth::token_by_refreshtoken(&self.token.as_ref().unwrap().refresh_token).await?;
-            self.update_token(new_token);
-
-            return Ok(self.token.as_ref().unwrap().access_token.clone());
+            let refresh_token = self.token.as_ref().unwrap().refresh_token.clone();
+            match self.oidc().await?.refresh_grant(&refresh_token).await {
+                Ok(new_token) => {
+                    self.accept_token(new_token).await?;
+                    return Ok(self.token.as_ref().unwrap().access_token.clone());
+                }
+                // Keycloak is unreachable or failing, a password login wouldn't get through either
+                Err(err) if !err.is_rejected() => return Err(err.into()),
+                Err(err) => {
+                    warn!("G-Portal refused the refresh token ({}), logging in with the password", err);
+                    self.clear_token();
+                }
+            }
         }
 
         // Neither access token nor refresh token are valid so let's login
+        self.login().await?;
+
+        Ok(self.token.as_ref().unwrap().access_token.clone())
+    }
+
+    async fn login(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(blocked_until) = self.login_blocked_until.filter(|until| *until > Utc::now()) {
+            return Err(anyhow::anyhow!(
+                "G-Portal refused the credentials of {}, not logging in again before {}",
+                self.username,
+                blocked_until
+            ));
+        }
+
         debug!("Neither access token nor refresh token are valid so logging in with {}", self.username);
 
-        let mut totp_code: String = "".to_string();
-        if self.totp_secret.is_some() {
-            totp_code = GPortalAuth::get_totp_code(&self.totp_secret.as_ref().unwrap())?;
+        let mut result = self.password_login(self.server_now()).await;
+
+        // The code may have been generated just before a step boundary or with a drifting clock, try once more.
+        // Keycloak refuses a wrong code of a direct grant as invalid credentials, so those get another try too.
+        let refused_at = match &result {
+            Err(AuthError::InvalidTotp { server_time, .. } | AuthError::InvalidCredentials { server_time, .. }) => {
+                Some(*server_time)
+            }
+            _ => None,
+        };
+        if let (Some(totp), Some(server_time)) = (self.totp.clone(), refused_at) {
+            let drift = server_time.map(|server_time| self.update_clock_offset(server_time)).unwrap_or(false);
+            let step = if drift {
+                self.server_now()
+            } else {
+                self.server_now() + totp.period()
+            };
+
+            debug!("G-Portal refused the TOTP code, retrying with the code of {}", step);
+            result = self.password_login(step).await;
+        }
+
+        match result {
+            Ok(token) => {
+                self.login_blocked_until = None;
+                self.accept_token(token).await
+            }
+            Err(err @ (AuthError::InvalidCredentials { .. } | AuthError::AccountLocked(_))) => {
+                self.login_blocked_until = Some(Utc::now() + Duration::seconds(LOGIN_BACKOFF_SECS));
+                Err(anyhow::Error::new(err).context(format!("Failed to log in to G-Portal as {}", self.username)))
+            }
+            Err(err @ AuthError::InvalidTotp { .. }) => Err(anyhow::Error::new(err)
+                .context("G-Portal refused the TOTP code, check TOTP_SECRET and the system clock")),
+            Err(err) => Err(anyhow::Error::new(err).context(format!("Failed to log in to G-Portal as {}", self.username))),
+        }
+    }
+
+    /// Fetches a new access token even if the current one still looks valid, e.g. after the API rejected it
+    pub async fn refresh_access_token(&mut self) -> Result<String, anyhow::Error> {
+        self.expire_access_token();
+        self.access_token().await
+    }
+
+    /// Time until the access token should be refreshed, `None` when there is no session to keep alive
+    pub fn time_until_refresh(&self) -> Option<Duration> {
+        if self.is_refresh_token_expired() {
+            return None;
+        }
+
+        let expire_time = self.fetch_time? + Duration::seconds(self.token.as_ref()?.expires_in);
+        Some((expire_time - self.leeway - Utc::now()).max(Duration::zero()))
+    }
+
+    /// Marks the current access token as expired so the next `access_token` call fetches a new one.
+    /// The refresh token is kept and will be used if it's still valid.
+    pub fn expire_access_token(&mut self) {
+        if let Some(token) = self.token.as_mut() {
+            token.expires_in = 0;
+        }
+    }
+
+    /// Revokes the session, e.g. on shutdown when it isn't persisted for the next start
+    pub async fn logout(&mut self) -> Result<(), anyhow::Error> {
+        if self.token.is_none() || self.is_refresh_token_expired() {
+            return Ok(());
+        }
+
+        let refresh_token = self.token.as_ref().unwrap().refresh_token.clone();
+        self.oidc().await?.revoke(&refresh_token).await?;
+        self.clear_token();
+        info!("Logged out of G-Portal");
+
+        Ok(())
+    }
+
+    /// Whether the session is kept for the next start
+    pub fn is_persisted(&self) -> bool {
+        self.token_store.is_some()
+    }
+
+    /// Account of the current session, known once a token has been validated
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    async fn accept_token(&mut self, token: Token) -> Result<(), anyhow::Error> {
+        if let Some(validator) = self.validator.as_mut() {
+            let identity = validator
+                .validate(&token)
+                .await
+                .map_err(|err| err.context("G-Portal issued a token that didn't pass validation"))?;
+
+            if self.identity.as_ref() != Some(&identity) {
+                info!(
+                    "Logged in to G-Portal as {} ({})",
+                    identity.email.as_deref().unwrap_or(&self.username),
+                    identity.account_id
+                );
+            }
+            self.identity = Some(identity);
         }
 
-        let token = GPortalAuth::token_by_password(&self.username, &self.password, &totp_code).await?;
         self.update_token(token);
+        Ok(())
+    }
 
-        Ok(self.token.as_ref().unwrap().access_token.clone())
+    fn clear_token(&mut self) {
+        self.token = None;
+        self.fetch_time = None;
+
+        if let Some(token_store) = &self.token_store {
+            if let Err(err) = token_store.clear() {
+                warn!("Failed to remove the stored G-Portal token: {}", err);
+            }
+        }
     }
 
     fn update_token(&mut self, token: Token) {
-        self.token = Some(token);
-        self.fetch_time = Some(Utc::now());
+        let fetch_time = Utc::now();
+
+        if let Some(token_store) = &self.token_store {
+            let stored = StoredToken {
+                username: self.username.clone(),
+                token,
+                fetch_time,
+            };
+            if let Err(err) = token_store.save(&stored) {
+                warn!("Failed to persist the G-Portal token: {}", err);
+            }
+
+            self.token = Some(stored.token);
+        } else {
+            self.token = Some(token);
+        }
+        self.fetch_time = Some(fetch_time);
     }
 
     fn is_token_expired(&self) -> bool {
@@ -132,7 +384,7 @@ impl GPortalAuth {
         let expire_time = self.fetch_time.unwrap() + Duration::seconds(self.token.as_ref().unwrap().expires_in);
         let date_now = Utc::now();
 
-        date_now >= expire_time
+        date_now + self.leeway >= expire_time
     }
 
     fn is_refresh_token_expired(&self) -> bool {
@@ -143,6 +395,153 @@ impl GPortalAuth {
         let expire_time = self.fetch_time.unwrap() + Duration::seconds(self.token.as_ref().unwrap().refresh_expires_in);
         let date_now = Utc::now();
 
-        date_now >= expire_time
+        date_now + self.leeway >= expire_time
+    }
+}
+
+/// Refreshes the access token in the background shortly before it expires, so polls don't have to wait for it.
+/// Once the session can't be refreshed anymore the next `access_token` call logs in again.
+pub fn spawn_refresh_task(auth: Arc<Mutex<GPortalAuth>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let wait = auth
+                .lock()
+                .await
+                .time_until_refresh()
+                .unwrap_or_else(|| Duration::seconds(IDLE_REFRESH_INTERVAL_SECS))
+                .max(Duration::seconds(MIN_REFRESH_INTERVAL_SECS));
+            tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+
+            let mut auth = auth.lock().await;
+            if auth.time_until_refresh().is_some_and(|wait| wait <= Duration::zero()) {
+                debug!("Refreshing the G-Portal access token ahead of expiry");
+                if let Err(err) = auth.access_token().await {
+                    warn!("Failed to refresh the G-Portal access token: {}", err);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openid::tests::{mount_discovery, token_response};
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn auth(expires_in: i64, refresh_expires_in: i64) -> GPortalAuth {
+        let mut token: Token = serde_json::from_value(token_response("access", "id")).unwrap();
+        token.expires_in = expires_in;
+        token.refresh_expires_in = refresh_expires_in;
+
+        let mut auth = GPortalAuth::new("xfileFIN".to_string(), "password".to_string());
+        auth.token = Some(token);
+        auth.fetch_time = Some(Utc::now());
+        auth
+    }
+
+    #[test]
+    fn test_leeway() {
+        let auth = auth(300, 1800);
+        assert!(!auth.is_token_expired());
+        assert!(auth.time_until_refresh().unwrap() > Duration::seconds(260));
+
+        let auth = auth.with_leeway(Duration::seconds(300));
+        assert!(auth.is_token_expired());
+        assert!(!auth.is_refresh_token_expired());
+        assert_eq!(auth.time_until_refresh(), Some(Duration::zero()));
+
+        let auth = self::auth(20, 20);
+        assert!(auth.is_token_expired());
+        assert!(auth.is_refresh_token_expired());
+        assert_eq!(auth.time_until_refresh(), None);
+    }
+
+    #[tokio::test]
+    async fn test_password_fallback() {
+        let server = MockServer::start().await;
+        mount_discovery(&server).await;
+
+        Mock::given(method("POST"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .set_body_json(json!({ "error": "invalid_grant", "error_description": "Session not active" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("grant_type=password"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(token_response("new", "id")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut auth = auth(0, 1800).with_issuer(&server.uri());
+        assert_eq!(auth.access_token().await.unwrap(), "new");
+    }
+
+    #[tokio::test]
+    async fn test_refused_credentials() {
+        let server = MockServer::start().await;
+        mount_discovery(&server).await;
+
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_json(json!({ "error": "invalid_grant", "error_description": "Invalid user credentials" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut auth = GPortalAuth::new("xfileFIN".to_string(), "wrong".to_string()).with_issuer(&server.uri());
+        let err = auth.access_token().await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AuthError::InvalidCredentials { .. })));
+
+        // Not retried before the backoff is over
+        assert!(auth.access_token().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_totp_retry() {
+        let server = MockServer::start().await;
+        mount_discovery(&server).await;
+
+        // G-Portal's clock is two minutes ahead, Keycloak refuses the code like a wrong password
+        let server_time = Utc::now() + Duration::seconds(120);
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .insert_header("Date", server_time.to_rfc2822().replace("+0000", "GMT").as_str())
+                    .set_body_json(json!({ "error": "invalid_grant", "error_description": "Invalid user credentials" })),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(token_response("new", "id")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let totp = TotpConfig::parse("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        let mut auth = GPortalAuth::new_with_totp("xfileFIN".to_string(), "password".to_string(), totp)
+            .with_issuer(&server.uri());
+        assert_eq!(auth.access_token().await.unwrap(), "new");
+        assert!((auth.clock_offset - Duration::seconds(120)).num_seconds().abs() <= 2);
+
+        let requests: Vec<_> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.method == wiremock::http::Method::Post)
+            .collect();
+        assert_eq!(requests.len(), 2);
+        assert_ne!(requests[0].body, requests[1].body);
     }
 }
@@ -1,98 +1,543 @@
-use chrono::{DateTime, Utc};
+use api::{GPortalClient, PricingPolicy, PurposeParser, SortColumn, SortOrder, StopAt, Transaction, TransactionsQuery};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use futures::{pin_mut, TryStreamExt};
+use serde::{Deserialize, Deserializer};
 use std::fs::{self};
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
-use crate::{gportal_auth::GPortalAuth, discord};
+use crate::{
+    gportal_auth::GPortalAuth,
+    notifier::{DonationContext, Notification, Notifier},
+    outbox::{OutboxEntry, RetryPolicy},
+    routing::Router,
+    state::{NotificationStatus, StateStore},
+};
 
-const CONFIG_PATH: &str = r#"./data/donations_last_fetch.txt"#;
+const LEGACY_LAST_FETCH_PATH: &str = r#"./data/donations_last_fetch.txt"#;
+
+/// What to do with the existing history when there is no donation state yet, `[first_run]` section of the config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FirstRunPolicy {
+    /// Treat everything that already exists as old
+    #[default]
+    Skip,
+    /// Announce the latest `count` donations
+    Last { count: usize },
+    /// Announce every donation after the given time
+    Since {
+        #[serde(deserialize_with = "deserialize_since")]
+        since: DateTime<Utc>,
+    },
+}
+
+/// Parses an RFC 3339 timestamp, or a plain `YYYY-MM-DD` date meaning midnight UTC
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| anyhow::anyhow!("Invalid time {}, expected an RFC 3339 timestamp or YYYY-MM-DD", value))?;
+
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+    Ok(Utc.from_utc_datetime(&midnight))
+}
+
+fn deserialize_since<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_since(&value).map_err(serde::de::Error::custom)
+}
 
 pub struct GPortalDonations {
-    auth: GPortalAuth,
-    webhook_url: String,
+    auth: Arc<Mutex<GPortalAuth>>,
+    client: GPortalClient,
+    pricing: PricingPolicy,
+    purpose_parser: PurposeParser,
+    notifiers: Vec<Box<dyn Notifier>>,
+    router: Router,
+    state: Box<dyn StateStore>,
+    first_run: FirstRunPolicy,
+    retry: RetryPolicy,
     last_fetch: Option<DateTime<Utc>>,
 }
 
 impl GPortalDonations {
-    pub fn new(auth: GPortalAuth, webhook_url: String) -> Self {
+    pub fn new(
+        auth: Arc<Mutex<GPortalAuth>>,
+        client: GPortalClient,
+        pricing: PricingPolicy,
+        purpose_parser: PurposeParser,
+        state: Box<dyn StateStore>,
+        notifiers: Vec<Box<dyn Notifier>>,
+    ) -> Self {
         GPortalDonations {
             auth,
-            webhook_url,
-            last_fetch: GPortalDonations::get_last_fetch().unwrap_or(None),
+            client,
+            pricing,
+            purpose_parser,
+            notifiers,
+            router: Router::default(),
+            state,
+            first_run: FirstRunPolicy::default(),
+            retry: RetryPolicy::default(),
+            last_fetch: None,
         }
     }
 
+    /// Continues from the timestamp older versions kept, when there is no ID based state yet
+    pub fn with_legacy_last_fetch(mut self) -> Self {
+        self.last_fetch = GPortalDonations::get_legacy_last_fetch().unwrap_or(None);
+        self
+    }
+
+    pub fn with_first_run(mut self, first_run: FirstRunPolicy) -> Self {
+        self.first_run = first_run;
+        self
+    }
+
+    pub fn with_router(mut self, router: Router) -> Self {
+        router.check_sinks(&self.sink_names());
+        self.router = router;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
     pub async fn check_new_donations(&mut self) -> Result<(), anyhow::Error> {
-        let access_token = self.auth.access_token().await?;
-        let donations = api::get_transactions(&access_token).await?.get_donations();
+        self.retry_failed_notifications().await?;
 
-        let last_fetch = if self.last_fetch.is_some() {
-            self.last_fetch.unwrap()
-        } else {
-            Utc::now()
+        // Transaction IDs are only ever handed out in increasing order, so walking the history
+        // by ID finds late or back-dated donations that a time-based check would skip
+        let query = TransactionsQuery::new().sort_by(SortColumn::Id, SortOrder::Descending);
+        let stop_at = match (self.state.high_water_mark()?, self.last_fetch) {
+            (Some(high_water_mark), _) => StopAt::TransactionId(high_water_mark.to_string()),
+            // Migrating from the timestamp based state
+            (None, Some(last_fetch)) => StopAt::Time(last_fetch),
+            // The history was empty on the first run, everything in it now is new
+            (None, None) if self.state.is_initialised()? => StopAt::End,
+            (None, None) => return self.first_run(query).await,
         };
-        for donation in donations.iter().rev() {
-            if donation.time_to_utc() > last_fetch {
-                info!(
-                    "New donation: {} - {} - {} ({} days) - {}",
-                    donation.id,
-                    donation.description,
-                    donation.amount_to_currency().to_string(),
-                    donation.amount_to_days(),
-                    donation.time_to_utc()
-                );
-
-                match discord::send_donation_webhook(&self.webhook_url, &donation).await {
-                    Ok(_) => (),
-                    Err(err) => error!("Error sending the Discord webhook from a Donation: {}", err),
+
+        let transactions = self.fetch_transactions(query, stop_at).await?;
+        self.process_transactions(transactions).await?;
+
+        self.last_fetch = None;
+
+        Ok(())
+    }
+
+    /// Announces every donation after `since` again, skipping the ones already sent.
+    /// Meant for replaying a window after an outage.
+    pub async fn backfill(&mut self, since: DateTime<Utc>) -> Result<(), anyhow::Error> {
+        let query = TransactionsQuery::new().sort_by(SortColumn::Id, SortOrder::Descending);
+        let transactions = self.fetch_transactions(query, StopAt::Time(since)).await?;
+
+        info!("Backfilling {} transactions since {}", transactions.len(), since);
+        let mut deliveries = Vec::new();
+        for transaction in transactions.iter().rev() {
+            if !transaction.is_donation() {
+                self.state.record_transaction(transaction, None)?;
+                continue;
+            }
+
+            let notifications = self
+                .state
+                .get_transaction(&transaction.id)?
+                .map(|seen| seen.notifications)
+                .unwrap_or_default();
+            let mut sinks = Vec::new();
+            for sink in self.donation_sinks(transaction) {
+                if notifications.get(&sink) == Some(&NotificationStatus::Sent) {
+                    debug!("Donation {} has already been sent to {}", transaction.id, sink);
+                    continue;
                 }
+
+                info!("Backfilling donation {} to {}", transaction.id, sink);
+                sinks.push(sink);
             }
-            else {
-                debug!(
-                    "Old donation: {} - {} - {} ({} days) - {}",
-                    donation.id,
-                    donation.description,
-                    donation.amount_to_currency().to_string(),
-                    donation.amount_to_days(),
-                    donation.time_to_utc()
-                );
+            deliveries.extend(self.queue_donation(transaction, &sinks)?);
+        }
+
+        self.deliver(deliveries).await
+    }
+
+    fn sink_names(&self) -> Vec<String> {
+        self.notifiers.iter().map(|notifier| notifier.name().to_string()).collect()
+    }
+
+    /// Sinks the routes send the donation to
+    fn donation_sinks(&self, donation: &Transaction) -> Vec<String> {
+        let context = DonationContext::new(donation, &self.pricing, &self.purpose_parser);
+
+        self.router
+            .route(&context, &self.sink_names())
+            .into_iter()
+            .map(|destination| destination.sink)
+            .collect()
+    }
+
+    /// Records the donation with its notifications queued in the outbox before anything is sent, so a
+    /// crash or shutdown while they're being sent retries them on the next start instead of losing them
+    fn queue_donation(&mut self, donation: &Transaction, sinks: &[String]) -> Result<Vec<(Transaction, OutboxEntry)>, anyhow::Error> {
+        let entries: Vec<OutboxEntry> = sinks.iter().map(|sink| OutboxEntry::new(&donation.id, sink)).collect();
+        let days = donation.amount_to_days(&self.pricing);
+        self.state.queue_notifications(donation, Some(days), &entries)?;
+
+        Ok(entries.into_iter().map(|entry| (donation.clone(), entry)).collect())
+    }
+
+    /// Records the transactions not seen before and announces the donations among them, oldest first
+    async fn process_transactions(&mut self, transactions: Vec<Transaction>) -> Result<(), anyhow::Error> {
+        let mut new_transactions: Vec<Transaction> = Vec::new();
+        for transaction in transactions {
+            if !self.state.is_seen(&transaction.id)? {
+                new_transactions.push(transaction);
+            }
+        }
+
+        debug!("Found {} new transactions", new_transactions.len());
+        let mut deliveries = Vec::new();
+        for transaction in new_transactions.into_iter().rev() {
+            if transaction.is_donation() {
+                deliveries.extend(self.announce_donation(&transaction)?);
+            } else {
+                self.state.record_transaction(&transaction, None)?;
+            }
+        }
+
+        self.deliver(deliveries).await
+    }
+
+    fn announce_donation(&mut self, donation: &Transaction) -> Result<Vec<(Transaction, OutboxEntry)>, anyhow::Error> {
+        info!(
+            "New donation: {} - {} - {} ({} days) - {}",
+            donation.id,
+            donation.description,
+            donation.amount,
+            donation.amount_to_days(&self.pricing),
+            donation.time_to_utc()
+        );
+
+        let sinks = self.donation_sinks(donation);
+        if sinks.is_empty() {
+            info!("No route matched donation {}, not announcing it", donation.id);
+        }
+
+        self.queue_donation(donation, &sinks)
+    }
+
+    /// Sends the notifications from the outbox whose next attempt is due, including the ones a
+    /// previous run queued but didn't get to send
+    async fn retry_failed_notifications(&mut self) -> Result<(), anyhow::Error> {
+        let sinks = self.sink_names();
+        let mut deliveries = Vec::new();
+        for entry in self.state.due_outbox_entries(Utc::now())? {
+            // Kept around in case the sink is configured again
+            if !sinks.contains(&entry.sink) {
+                continue;
             }
+
+            let transaction = match self.state.get_transaction(&entry.transaction_id)? {
+                Some(seen) => seen.transaction,
+                None => {
+                    warn!("Dropping retry of unknown transaction {}", entry.transaction_id);
+                    self.state.remove_outbox_entry(&entry.transaction_id, &entry.sink)?;
+                    continue;
+                }
+            };
+
+            info!(
+                "Retrying {} notification of donation {} (attempt {})",
+                entry.sink,
+                entry.transaction_id,
+                entry.attempts + 1
+            );
+            deliveries.push((transaction, entry));
         }
 
-        self.last_fetch = Some(Utc::now());
-        self.save_last_fetch()?;
+        self.deliver(deliveries).await
+    }
+
+    /// Hands the notifications to their sinks, one batch per sink, and removes the delivered ones from the outbox
+    async fn deliver(&mut self, deliveries: Vec<(Transaction, OutboxEntry)>) -> Result<(), anyhow::Error> {
+        let mut deliveries = deliveries;
+
+        for i in 0..self.notifiers.len() {
+            let (batch, rest): (Vec<_>, Vec<_>) = deliveries
+                .into_iter()
+                .partition(|(_, entry)| entry.sink == self.notifiers[i].name());
+            deliveries = rest;
+            if batch.is_empty() {
+                continue;
+            }
+
+            let notifications: Vec<Notification> = batch
+                .iter()
+                .map(|(donation, entry)| {
+                    let context = DonationContext::new(donation, &self.pricing, &self.purpose_parser);
+                    Notification {
+                        template: self.router.template_for(&context, &entry.sink),
+                        donation: context,
+                    }
+                })
+                .collect();
+            let results = self.notifiers[i].notify(&notifications).await;
+
+            for ((donation, entry), result) in batch.into_iter().zip(results) {
+                self.handle_delivery(&donation, entry, result)?;
+            }
+        }
 
         Ok(())
     }
 
-    fn ensure_datadir_exists() -> Result<(), anyhow::Error> {
-        if let Some(p) = std::path::Path::new(&CONFIG_PATH).parent() { fs::create_dir_all(p)? };
+    fn handle_delivery(
+        &mut self,
+        donation: &Transaction,
+        mut entry: OutboxEntry,
+        result: Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let status = match result {
+            Ok(_) => {
+                // Nothing left to retry, also clears retries queued before a backfill sent the notification
+                self.state.remove_outbox_entry(&entry.transaction_id, &entry.sink)?;
+                NotificationStatus::Sent
+            }
+            Err(err) => {
+                entry.failed(&err.to_string(), &self.retry, Utc::now());
+                self.state.save_outbox_entry(&entry)?;
+
+                if entry.dead {
+                    error!(
+                        "Giving up on the {} notification of donation {} after {} attempts: {}",
+                        entry.sink, donation.id, entry.attempts, err
+                    );
+                    NotificationStatus::DeadLetter
+                } else {
+                    error!(
+                        "Error sending the {} notification of donation {}, retrying at {}: {}",
+                        entry.sink, donation.id, entry.next_attempt_at, err
+                    );
+                    NotificationStatus::Failed
+                }
+            }
+        };
+
+        self.state.set_notification_status(&donation.id, &entry.sink, status)
+    }
+
+    async fn first_run(&mut self, query: TransactionsQuery) -> Result<(), anyhow::Error> {
+        let transactions = match self.first_run.clone() {
+            FirstRunPolicy::Skip => Vec::new(),
+            FirstRunPolicy::Last { count } => {
+                info!("No donation state found, announcing the last {} donations", count);
+                self.fetch_last_donations(query.clone(), count).await?
+            }
+            FirstRunPolicy::Since { since } => {
+                info!("No donation state found, announcing donations since {}", since);
+                self.fetch_transactions(query.clone(), StopAt::Time(since)).await?
+            }
+        };
+        self.process_transactions(transactions).await?;
+
+        // Nothing to announce, remember where the history ends
+        if self.state.high_water_mark()?.is_none() {
+            self.mark_existing_as_seen(query).await?;
+        }
+
+        // Keeps later polls out of the first run even when there was no history to remember
+        self.state.mark_initialised()
+    }
+
+    /// Newest transactions up to and including the `count`th donation
+    async fn fetch_last_donations(&mut self, query: TransactionsQuery, count: usize) -> Result<Vec<Transaction>, anyhow::Error> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let access_token = self.access_token().await?;
+        let result = match collect_last_donations(&self.client, &access_token, query.clone(), count).await {
+            Err(err) if err.is_unauthorized() => {
+                let access_token = self.refresh_access_token().await?;
+                collect_last_donations(&self.client, &access_token, query, count).await
+            }
+            result => result,
+        };
+
+        result.map_err(|err| self.handle_fetch_error(err))
+    }
+
+    /// First run, treat everything that already exists as old
+    async fn mark_existing_as_seen(&mut self, query: TransactionsQuery) -> Result<(), anyhow::Error> {
+        // Only the newest transaction is needed, walking the history would take a request per transaction
+        let query = query.page_size(1);
+        let access_token = self.access_token().await?;
+        let result = match self.client.get_transactions_page(&access_token, &query).await {
+            Err(err) if err.is_unauthorized() => {
+                let access_token = self.refresh_access_token().await?;
+                self.client.get_transactions_page(&access_token, &query).await
+            }
+            result => result,
+        };
+        let page = result.map_err(|err| self.handle_fetch_error(err))?;
+        let latest = page.transactions.first();
+
+        info!(
+            "No donation state found, announcing donations after transaction {}",
+            latest.map_or("-", |transaction| transaction.id.as_str())
+        );
+        if let Some(latest) = latest {
+            self.state.record_transaction(latest, None)?;
+        }
 
         Ok(())
     }
 
-    fn get_last_fetch() -> Result<Option<DateTime<Utc>>, anyhow::Error> {
-        GPortalDonations::ensure_datadir_exists()?;
+    async fn fetch_transactions(&mut self, query: TransactionsQuery, stop_at: StopAt) -> Result<Vec<Transaction>, anyhow::Error> {
+        let access_token = self.access_token().await?;
+        let result = match self.client.get_transactions_until(&access_token, query.clone(), stop_at.clone()).await {
+            Err(err) if err.is_unauthorized() => {
+                let access_token = self.refresh_access_token().await?;
+                self.client.get_transactions_until(&access_token, query, stop_at).await
+            }
+            result => result,
+        };
+
+        result.map_err(|err| self.handle_fetch_error(err))
+    }
 
-        let content: String = fs::read_to_string(CONFIG_PATH)?.parse()?;
+    async fn access_token(&self) -> Result<String, anyhow::Error> {
+        self.auth.lock().await.access_token().await
+    }
+
+    /// Fetches a new access token after G-Portal rejected the current one
+    async fn refresh_access_token(&self) -> Result<String, anyhow::Error> {
+        warn!("G-Portal rejected the access token, retrying once with a refreshed one");
+        self.auth.lock().await.refresh_access_token().await
+    }
 
-        let result = content.parse::<DateTime<Utc>>();
-        if result.is_err() {
+    fn handle_fetch_error(&self, err: api::Error) -> anyhow::Error {
+        if err.is_unauthorized() {
+            anyhow::Error::new(err).context("G-Portal rejected the refreshed access token too")
+        } else if err.is_schema_error() {
+            anyhow::Error::new(err)
+                .context("G-Portal transactions didn't match the expected format, the API has probably changed")
+        } else {
+            err.into()
+        }
+    }
+
+    /// Timestamp written by older versions, only used to migrate to the ID based state
+    fn get_legacy_last_fetch() -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+        if !std::path::Path::new(LEGACY_LAST_FETCH_PATH).exists() {
             return Ok(None);
         }
 
-        Ok(Some(result.unwrap()))
+        let content = fs::read_to_string(LEGACY_LAST_FETCH_PATH)?;
+
+        Ok(content.trim().parse::<DateTime<Utc>>().ok())
+    }
+}
+
+/// Walks the history until `count` donations have been found
+async fn collect_last_donations(
+    client: &GPortalClient,
+    access_token: &str,
+    query: TransactionsQuery,
+    count: usize,
+) -> Result<Vec<Transaction>, api::Error> {
+    let stream = client.transactions(access_token, query, StopAt::End);
+    pin_mut!(stream);
+
+    let mut transactions = Vec::new();
+    let mut donations = 0;
+    while let Some(transaction) = stream.try_next().await? {
+        if transaction.is_donation() {
+            donations += 1;
+        }
+        transactions.push(transaction);
+
+        if donations >= count {
+            break;
+        }
     }
 
-    fn save_last_fetch(&self) -> Result<(), anyhow::Error> {
-        GPortalDonations::ensure_datadir_exists()?;
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openid::tests::token_response;
+    use crate::state::SqliteStateStore;
+    use async_trait::async_trait;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Remembers the IDs of the donations it was handed
+    struct RecordingNotifier {
+        sent: Arc<std::sync::Mutex<Vec<String>>>,
+    }
 
-        if self.last_fetch.is_none() {
-            return Ok(());
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        fn name(&self) -> &str {
+            "recording"
         }
 
-        let last_fetch = self.last_fetch.unwrap();
+        async fn send(&mut self, notification: &Notification) -> Result<(), anyhow::Error> {
+            self.sent.lock().unwrap().push(notification.donation.id.clone());
+            Ok(())
+        }
+    }
 
-        fs::write(CONFIG_PATH, last_fetch.to_rfc3339())?;
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("2022-10-01").unwrap().to_rfc3339(), "2022-10-01T00:00:00+00:00");
+        assert_eq!(
+            parse_since("2022-10-01T21:50:01+02:00").unwrap().to_rfc3339(),
+            "2022-10-01T19:50:01+00:00"
+        );
+        assert!(parse_since("yesterday").is_err());
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_first_donation_after_empty_history() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{ "grid": [], "total": 0 }"#))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{ "grid": [["14500000", "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-01T21:50:01+02:00"]], "total": 1 }"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let token = serde_json::from_value(token_response("access", "id")).unwrap();
+        let auth = GPortalAuth::new("xfileFIN".to_string(), "password".to_string()).with_token(token);
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let notifier = RecordingNotifier { sent: sent.clone() };
+        let mut donations = GPortalDonations::new(
+            Arc::new(Mutex::new(auth)),
+            GPortalClient::new().with_base_url(server.uri()),
+            PricingPolicy::default(),
+            PurposeParser::default(),
+            Box::new(SqliteStateStore::in_memory().unwrap()),
+            vec![Box::new(notifier)],
+        );
+
+        donations.check_new_donations().await.unwrap();
+        assert!(sent.lock().unwrap().is_empty());
+
+        donations.check_new_donations().await.unwrap();
+        assert_eq!(*sent.lock().unwrap(), vec!["14500000".to_string()]);
     }
 }
@@ -0,0 +1,419 @@
+use std::fs;
+
+use chrono::Utc;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+
+use crate::openid::{ProviderMetadata, Token};
+
+/// Scope the G-Portal API requires from the access token
+pub const REQUIRED_SCOPE: &str = r#"gportal"#;
+/// Accepted difference in seconds between our clock and the issuer's when checking expiry
+const EXPIRY_LEEWAY_SECS: i64 = 60;
+
+/// JSON Web Key, only the RSA and EC P-256 parameters are used
+#[derive(Debug, Clone, Deserialize)]
+pub struct Jwk {
+    pub kid: Option<String>,
+    pub kty: String,
+    pub alg: Option<String>,
+    #[serde(rename = "use")]
+    pub key_use: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+    pub crv: Option<String>,
+    pub x: Option<String>,
+    pub y: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    pub async fn fetch(url: &str) -> Result<Jwks, anyhow::Error> {
+        Ok(reqwest::get(url).await?.error_for_status()?.json().await?)
+    }
+
+    pub fn from_file(path: &str) -> Result<Jwks, anyhow::Error> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn find(&self, kid: Option<&str>) -> Option<&Jwk> {
+        self.keys
+            .iter()
+            .filter(|key| key.key_use.as_deref().unwrap_or("sig") == "sig")
+            .find(|key| kid.is_none() || key.kid.as_deref() == kid)
+    }
+}
+
+/// Where the signing keys come from
+#[derive(Debug, Clone)]
+pub enum JwksSource {
+    /// The `jwks_uri` of the provider, fetched again when a token is signed with an unknown key
+    Discovery,
+    File(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// Claims of a Keycloak access or ID token that the integration cares about
+#[derive(Debug, Clone, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iss: Option<String>,
+    pub exp: i64,
+    pub email: Option<String>,
+    /// Space separated, only in access tokens
+    #[serde(default)]
+    pub scope: String,
+    pub aud: Option<Audience>,
+    /// Client the token was issued to
+    pub azp: Option<String>,
+}
+
+/// `aud` claim, a single audience or a list of them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(one) => one == audience,
+            Audience::Many(many) => many.iter().any(|one| one == audience),
+        }
+    }
+}
+
+/// The access token was issued without the scope the G-Portal API needs, logging in again won't help
+#[derive(Debug, thiserror::Error)]
+#[error("Access token lacks the {} scope, granted scopes: {granted}", REQUIRED_SCOPE)]
+pub struct MissingScope {
+    pub granted: String,
+}
+
+/// Account the tokens were issued to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub account_id: String,
+    pub email: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+/// Verifies the signatures and claims of the tokens G-Portal's Keycloak issues
+pub struct TokenValidator {
+    source: JwksSource,
+    jwks: Option<Jwks>,
+    jwks_uri: Option<String>,
+    issuer: Option<String>,
+    client_id: Option<String>,
+}
+
+impl TokenValidator {
+    pub fn new(source: JwksSource) -> Self {
+        TokenValidator {
+            source,
+            jwks: None,
+            jwks_uri: None,
+            issuer: None,
+            client_id: None,
+        }
+    }
+
+    /// Uses the keys of the discovered provider and rejects tokens with another `iss` claim,
+    /// or that were issued to another client than `client_id`
+    pub fn set_provider(&mut self, metadata: &ProviderMetadata, client_id: &str) {
+        self.issuer = Some(metadata.issuer.clone());
+        self.jwks_uri = Some(metadata.jwks_uri.clone());
+        self.client_id = Some(client_id.to_string());
+    }
+
+    /// Verifies the access token and the ID token, and checks that the `gportal` scope was granted
+    pub async fn validate(&mut self, token: &Token) -> Result<Identity, anyhow::Error> {
+        let access = self.decode(&token.access_token).await?;
+        let id = match token.id_token.is_empty() {
+            true => None,
+            false => Some(self.decode(&token.id_token).await?),
+        };
+
+        if let Some(id) = id.as_ref().filter(|id| id.sub != access.sub) {
+            return Err(anyhow::anyhow!(
+                "ID token is for account {} but the access token for {}",
+                id.sub,
+                access.sub
+            ));
+        }
+        if let Some(client_id) = &self.client_id {
+            // The ID token has to name us as its audience, Keycloak's access tokens only name the client in `azp`
+            if let Some(id) = id.as_ref().filter(|id| !id.aud.as_ref().is_some_and(|aud| aud.contains(client_id))) {
+                return Err(anyhow::anyhow!("ID token was issued for {:?} instead of {}", id.aud, client_id));
+            }
+            for claims in std::iter::once(&access).chain(id.as_ref()) {
+                if claims.azp.as_ref().is_some_and(|azp| azp != client_id) {
+                    return Err(anyhow::anyhow!("Token was issued to {:?} instead of {}", claims.azp, client_id));
+                }
+            }
+        }
+
+        let scopes: Vec<String> = access.scope.split_whitespace().map(|scope| scope.to_string()).collect();
+        if !scopes.iter().any(|scope| scope == REQUIRED_SCOPE) {
+            return Err(MissingScope { granted: access.scope }.into());
+        }
+
+        Ok(Identity {
+            account_id: access.sub,
+            email: id.and_then(|id| id.email).or(access.email),
+            scopes,
+        })
+    }
+
+    /// Verifies the signature, issuer and expiry of a JWT and returns its claims
+    pub async fn decode(&mut self, jwt: &str) -> Result<Claims, anyhow::Error> {
+        let mut parts = jwt.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature), None) => (header, payload, signature),
+            _ => return Err(anyhow::anyhow!("Token is not a JWT")),
+        };
+
+        let header: Header = serde_json::from_slice(&decode_part(header)?)?;
+        let key = self.key(header.kid.as_deref()).await?;
+        let message = &jwt[..jwt.len() - signature.len() - 1];
+        verify(&header.alg, &key, message.as_bytes(), &decode_part(signature)?)?;
+
+        let claims: Claims = serde_json::from_slice(&decode_part(payload)?)?;
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                return Err(anyhow::anyhow!("Token was issued by {:?} instead of {}", claims.iss, issuer));
+            }
+        }
+        if claims.exp + EXPIRY_LEEWAY_SECS < Utc::now().timestamp() {
+            return Err(anyhow::anyhow!("Token has expired"));
+        }
+
+        Ok(claims)
+    }
+
+    async fn key(&mut self, kid: Option<&str>) -> Result<Jwk, anyhow::Error> {
+        if let Some(key) = self.jwks.as_ref().and_then(|jwks| jwks.find(kid)) {
+            return Ok(key.clone());
+        }
+
+        // Unknown key, Keycloak has probably rotated its keys
+        let jwks = match (&self.source, &self.jwks_uri) {
+            (JwksSource::Discovery, Some(url)) => {
+                debug!("Fetching the signing keys from {}", url);
+                Jwks::fetch(url).await?
+            }
+            (JwksSource::Discovery, None) => return Err(anyhow::anyhow!("OpenID provider hasn't been discovered")),
+            (JwksSource::File(path), _) => Jwks::from_file(path)?,
+        };
+        let key = jwks.find(kid).cloned();
+        self.jwks = Some(jwks);
+
+        key.ok_or_else(|| anyhow::anyhow!("No signing key {} in the JWKS", kid.unwrap_or("-")))
+    }
+}
+
+fn decode_part(part: &str) -> Result<Vec<u8>, anyhow::Error> {
+    Ok(base64::decode_config(part, base64::URL_SAFE_NO_PAD)?)
+}
+
+fn jwk_param(value: &Option<String>, name: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let value = value.as_deref().ok_or_else(|| anyhow::anyhow!("JWK has no {}", name))?;
+    decode_part(value)
+}
+
+fn verify(alg: &str, key: &Jwk, message: &[u8], signature: &[u8]) -> Result<(), anyhow::Error> {
+    if key.alg.as_deref().is_some_and(|key_alg| key_alg != alg) {
+        return Err(anyhow::anyhow!("Token is signed with {} but the key is for {:?}", alg, key.alg));
+    }
+
+    let result = match (alg, key.kty.as_str()) {
+        ("RS256", "RSA") | ("RS384", "RSA") | ("RS512", "RSA") => {
+            let params = match alg {
+                "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+            };
+            let (n, e) = (jwk_param(&key.n, "n")?, jwk_param(&key.e, "e")?);
+            RsaPublicKeyComponents { n: &n, e: &e }.verify(params, message, signature)
+        }
+        ("ES256", "EC") if key.crv.as_deref() == Some("P-256") => {
+            // Uncompressed point
+            let mut point = vec![0x04];
+            point.extend(jwk_param(&key.x, "x")?);
+            point.extend(jwk_param(&key.y, "y")?);
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(message, signature)
+        }
+        _ => return Err(anyhow::anyhow!("Unsupported token algorithm {} for a {} key", alg, key.kty)),
+    };
+
+    result.map_err(|_| anyhow::anyhow!("Token signature is invalid"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openid::tests::token_response;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, RsaKeyPair};
+    use serde_json::json;
+
+    // Throwaway 2048 bit key generated for the tests
+    const RSA_KEY: &str = concat!(
+        "MIIEpAIBAAKCAQEAqnRcPDbCIPglBMqY9MkjnGiSMeF3+5BbcgOBGQ+JwbBZVM49jWHk5qH50AqjsnZISZ1+TxJ9JiNqCfnAaoIy",
+        "0NY+PO9G2G7n56c/t2HI3cliSoPwHcmAUIzf4oKTOe0ei4vKSoqK0EPABnP6P3PbFDmdIIaupjLa+Fop/MmOMsMJGeHTcR+uKQM2",
+        "h7deOer3/FZLKrcrWKN7svQp3qIBHg+J70lk5hlOzTMmZiA5oBMbYvGoJjT70zlzCY4H6wPbzlH8GlppuwggZPRWndHXHXhobaBI",
+        "gVE9+aEPmd1Tst8NPKCM1AFUaC+6mWxkVX0DbmuyuU/utOubma2AwrP2fwIDAQABAoIBAACXQP9Cb1TUQgiXYoVYByQSfA3pNVRt",
+        "ZoEUKwfjtRCzTvP2ImIm6Iy2G1q8bAnWfXSn3kQMO9vR0UXHPwKqKzPscjBy4eDRLZjwzE3olwd8cbdGvXfU9s67VkaqON7BXX2S",
+        "q/kAgZpW3x0GnOOdwAYnbE3XDwGWI9/VgYIV+W9f7+kKbvboHizuhfA3mQKUCqu5l0JNSq0UOEhh5hCSw2LOrFOoQRr7CN2tDXHo",
+        "UjKzWz74o1ICEuVeexZjdFbkQYdoP6Io+We/6XGgSH8b+P6KPECWgkSTW8zbRYBZwAXKaAQtiG7vSBLM0aT2dE80pKs8wmHQMgJt",
+        "r0k0Zf7ifzUCgYEA3GhuviEzbDa6iMypoXjoYuggxMGh2mDbfr/oI/Dk/b41zfxNubwTa1RbqwxuZpAIzd2uGh78zdpzW72Bthia",
+        "CBL3CEQkRFxHyftgqDjKGbAxdulJh7UxMBSIrZmvvr+nSGU+G5+Iwa7tvUWhvKDOh5UQOyP5d7SYgEBKRxf6k7UCgYEAxfrgM4su",
+        "dztPZhZ7W0Os19SeWOQMg3Mb6X0UKLUFvu2WuAIa7TNBmcvzVLRM2Hwp6rusyBaNXt6eIlaE1OgX1r2sC5rtMqQTUv0i2bb0dyrn",
+        "w7MwCWON4bPOKnS4Jhb74jwARjr9io4uJKPhQzqmvJgTlb2hywFrqFbtDEVPKeMCgYEAlhy+R9ne5vXDR4Z0hZgOVcaZAsiKDmxn",
+        "McVMOqZHn5XbmUYcPcKdSz0yDaYyzsGMbYFsoTe3oBAG/1VrMXVB+vvHqaaXOMPM1QDTDN9YrfQ1TDKItNev/3oMuIdyjayEJydl",
+        "DHU0NA2Kt5zkEc9HuknIuy6+KkwijT68F/152P0CgYEAwTBYvRNQqTpv9Q91Gg6EyCuKxPTrnQ2jLAlItZxHWhMforkzK0l79ybE",
+        "cGBsKEeo44x1Qt523x9eseU5Q0SjloGJM93fHi5rSO0Ip+hWzz5H4HinJFJILfk18cnptAlzi0S/g8imhiDNuCVOeDrwX1IKsnDU",
+        "Q3NEaYVBVVsAr78CgYBRoFRSxKMgzQ1MrHzjRPUhLbFljmtwcrCiTEwLDcanM7Y0dMlbGFkVjQn6TQ7y4iqi6ppG6oAsGMZ3Bb8S",
+        "HrJ0CC9Kqsh08AqHNd+jGUNvdPmYzCJcnHc6gnjj7abuz61XLJYBagulf5YyluoKFiaPLhkJu8AdC0ratK0c+LkgWQ==",
+    );
+    const ISSUER: &str = "https://auth.g-portal.com/auth/realms/master";
+
+    fn encode(value: &serde_json::Value) -> String {
+        base64::encode_config(value.to_string(), base64::URL_SAFE_NO_PAD)
+    }
+
+    fn claims(scope: &str) -> serde_json::Value {
+        json!({
+            "sub": "f3b3c0de-0000-4000-8000-000000000001",
+            "iss": ISSUER,
+            "exp": Utc::now().timestamp() + 300,
+            "email": "xfileFIN@xfileFIN.com",
+            "scope": scope,
+            "aud": ["website", "account"],
+            "azp": "website",
+        })
+    }
+
+    fn rsa_key() -> RsaKeyPair {
+        RsaKeyPair::from_der(&base64::decode(RSA_KEY).unwrap()).unwrap()
+    }
+
+    fn sign_rs256(key: &RsaKeyPair, claims: &serde_json::Value) -> String {
+        let message = format!("{}.{}", encode(&json!({ "alg": "RS256", "kid": "rsa" })), encode(claims));
+        let mut signature = vec![0; key.public_modulus_len()];
+        key.sign(&signature::RSA_PKCS1_SHA256, &SystemRandom::new(), message.as_bytes(), &mut signature)
+            .unwrap();
+
+        format!("{}.{}", message, base64::encode_config(signature, base64::URL_SAFE_NO_PAD))
+    }
+
+    /// Writes the JWKS to a temporary file and returns a validator reading it
+    fn validator(name: &str, jwks: serde_json::Value) -> TokenValidator {
+        let path = std::env::temp_dir().join(format!("gportal-jwks-{}-{}.json", name, std::process::id()));
+        fs::write(&path, jwks.to_string()).unwrap();
+
+        let mut validator = TokenValidator::new(JwksSource::File(path.to_str().unwrap().to_string()));
+        validator.set_provider(&ProviderMetadata {
+            issuer: ISSUER.to_string(),
+            token_endpoint: format!("{}/protocol/openid-connect/token", ISSUER),
+            jwks_uri: format!("{}/protocol/openid-connect/certs", ISSUER),
+            revocation_endpoint: None,
+            end_session_endpoint: None,
+        }, "website");
+        validator
+    }
+
+    fn rsa_jwks(key: &RsaKeyPair) -> serde_json::Value {
+        let public_key = key.public_key();
+        json!({ "keys": [{
+            "kid": "rsa", "kty": "RSA", "alg": "RS256", "use": "sig",
+            "n": base64::encode_config(public_key.modulus().big_endian_without_leading_zero(), base64::URL_SAFE_NO_PAD),
+            "e": base64::encode_config(public_key.exponent().big_endian_without_leading_zero(), base64::URL_SAFE_NO_PAD),
+        }]})
+    }
+
+    fn token(access_token: String, id_token: String) -> Token {
+        serde_json::from_value(token_response(&access_token, &id_token)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rs256_tokens() {
+        let key = rsa_key();
+        let mut validator = validator("rs256", rsa_jwks(&key));
+
+        let access_token = sign_rs256(&key, &claims("openid email profile gportal"));
+        let identity = validator.validate(&token(access_token.clone(), String::new())).await.unwrap();
+        assert_eq!(identity.account_id, "f3b3c0de-0000-4000-8000-000000000001");
+        assert_eq!(identity.email.as_deref(), Some("xfileFIN@xfileFIN.com"));
+        assert!(identity.scopes.contains(&"gportal".to_string()));
+
+        // Tampered payload
+        let mut parts: Vec<&str> = access_token.split('.').collect();
+        let forged = encode(&claims("openid gportal admin"));
+        parts[1] = &forged;
+        assert!(validator.decode(&parts.join(".")).await.is_err());
+
+        let id_token = sign_rs256(&key, &claims("openid"));
+        assert!(validator.validate(&token(access_token.clone(), id_token)).await.is_ok());
+
+        // Issued by the same realm to another client
+        let mut other_client = claims("openid");
+        other_client["aud"] = json!("other");
+        let id_token = sign_rs256(&key, &other_client);
+        assert!(validator.validate(&token(access_token.clone(), id_token)).await.is_err());
+        other_client["azp"] = json!("other");
+        let other_access_token = sign_rs256(&key, &other_client);
+        assert!(validator.validate(&token(other_access_token, String::new())).await.is_err());
+
+        let without_scope = sign_rs256(&key, &claims("openid email profile"));
+        let err = validator.validate(&token(without_scope, String::new())).await.unwrap_err();
+        assert!(err.downcast_ref::<MissingScope>().is_some());
+
+        let mut expired = claims("gportal");
+        expired["exp"] = json!(Utc::now().timestamp() - 3600);
+        assert!(validator.decode(&sign_rs256(&key, &expired)).await.is_err());
+
+        let mut other_issuer = claims("gportal");
+        other_issuer["iss"] = json!("https://evil.example.com/realms/master");
+        assert!(validator.decode(&sign_rs256(&key, &other_issuer)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_es256_token() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+
+        let point = key.public_key().as_ref();
+        let mut validator = validator(
+            "es256",
+            json!({ "keys": [{
+                "kid": "ec", "kty": "EC", "crv": "P-256",
+                "x": base64::encode_config(&point[1..33], base64::URL_SAFE_NO_PAD),
+                "y": base64::encode_config(&point[33..], base64::URL_SAFE_NO_PAD),
+            }]}),
+        );
+
+        let message = format!("{}.{}", encode(&json!({ "alg": "ES256", "kid": "ec" })), encode(&claims("gportal")));
+        let signature = key.sign(&rng, message.as_bytes()).unwrap();
+        let jwt = format!("{}.{}", message, base64::encode_config(signature.as_ref(), base64::URL_SAFE_NO_PAD));
+
+        let claims = validator.decode(&jwt).await.unwrap();
+        assert_eq!(claims.email.as_deref(), Some("xfileFIN@xfileFIN.com"));
+
+        // Signed with a key that isn't in the JWKS
+        let rsa_token = sign_rs256(&rsa_key(), &json!({ "sub": "x", "exp": 0 }));
+        assert!(validator.decode(&rsa_token).await.is_err());
+    }
+}
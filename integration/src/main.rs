@@ -4,14 +4,22 @@ extern crate log;
 use chrono::{DateTime, Utc};
 use chrono_tz::Tz;
 use dotenv::dotenv;
-use std::time::Duration;
-use tokio::time::sleep;
+use tokio::sync::watch;
 
+mod account;
+mod config;
 mod discord;
 mod gportal_auth;
 mod gportal_donations;
+mod jwt;
 mod logging;
+mod notifier;
 mod openid;
+mod outbox;
+mod routing;
+mod state;
+mod token_store;
+mod totp;
 
 fn get_timezone() -> Tz {
     let timezone = dotenv::var("CHRONO_TIMEZONE").unwrap_or("Europe/Helsinki".to_string());
@@ -26,6 +34,74 @@ fn get_time_after_duration(duration: u64) -> String {
     time.format("%Y-%m-%dT%H:%M:%S").to_string()
 }
 
+/// Value of a `--name value` or `--name=value` argument
+fn get_arg_value(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(&prefix).map(str::to_string)
+        }
+    })
+}
+
+/// Arguments that are not a `--name value` or `--name=value` pair, without the program name
+fn get_positional_args(args: &[String]) -> Vec<&str> {
+    let mut positional = Vec::new();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg.starts_with("--") {
+            if !arg.contains('=') {
+                iter.next();
+            }
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+
+    positional
+}
+
+fn print_history(state: &dyn state::StateStore, transaction_id: Option<&str>) -> Result<(), anyhow::Error> {
+    let transactions = match transaction_id {
+        Some(id) => state.get_transaction(id)?.into_iter().collect(),
+        None => state.transactions()?,
+    };
+
+    for seen in transactions {
+        let notifications: Vec<String> = seen
+            .notifications
+            .iter()
+            .map(|(sink, status)| format!("{}: {}", sink, status.as_str()))
+            .collect();
+
+        println!(
+            "{} | {} | {} | {} | {} days | {}",
+            seen.transaction.id,
+            seen.transaction.time.with_timezone(&get_timezone()).format("%Y-%m-%d %H:%M:%S"),
+            seen.transaction.amount,
+            seen.transaction.get_donator_and_purpose().0,
+            seen.vip_days.map_or("-".to_string(), |days| days.to_string()),
+            notifications.join(", "),
+        );
+    }
+
+    Ok(())
+}
+
+fn print_dead_letters(state: &dyn state::StateStore) -> Result<(), anyhow::Error> {
+    for entry in state.dead_letters()? {
+        println!(
+            "{} | {} | {} attempts | {}",
+            entry.transaction_id, entry.sink, entry.attempts, entry.last_error
+        );
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -34,44 +110,97 @@ async fn main() {
     info!("G-Portal Integrations starting");
     info!("Using time zone: {}", get_timezone().name());
 
-    let username = dotenv::var("GPORTAL_USERNAME").unwrap();
-    let password = dotenv::var("GPORTAL_PASSWORD").unwrap();
-    let totp_secret = dotenv::var("TOTP_SECRET").unwrap_or("".to_string());
+    let config = config::Config::load().unwrap();
+
+    // `gportal-integrations history [transaction id]` prints the recorded donation history,
+    // `--account <name>` picks the account, required when `[[accounts]]` are configured
+    let args: Vec<String> = std::env::args().collect();
+    let account = get_arg_value(&args, "--account");
+    let positional = get_positional_args(&args);
+    if positional.first() == Some(&"history") {
+        let state = config.state_for(account.as_deref()).unwrap().open().unwrap();
+        print_history(state.as_ref(), positional.get(1).copied()).unwrap();
+        return;
+    }
+    // `gportal-integrations dead-letters` prints the notifications that ran out of retries
+    if positional.first() == Some(&"dead-letters") {
+        let state = config.state_for(account.as_deref()).unwrap().open().unwrap();
+        print_dead_letters(state.as_ref()).unwrap();
+        return;
+    }
+    let backfill_since = get_arg_value(&args, "--backfill-since")
+        .map(|value| gportal_donations::parse_since(&value))
+        .transpose()
+        .unwrap();
+
     let donation_interval: u64 = dotenv::var("DONATION_INTERVAL")
         .map(|var| var.parse::<u64>())
         .unwrap_or(Ok(900_000))
         .unwrap();
 
-    let auth_client = if totp_secret.is_empty() {
-        gportal_auth::GPortalAuth::new(username, password)
-    } else {
-        gportal_auth::GPortalAuth::new_with_totp(username, password, totp_secret)
-    };
+    let accounts = account::Account::from_config(&config).unwrap();
+    if !accounts.is_empty() {
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            info!("Shutting down");
+            let _ = shutdown_sender.send(true);
+        });
 
-    let donation_webhook = dotenv::var("DISCORD_DONATION_WEBHOOK").unwrap_or("".to_string());
-    if !donation_webhook.is_empty() {
-        let mut donations = gportal_donations::GPortalDonations::new(auth_client, donation_webhook);
-        loop {
-            if let Err(err) = donations.check_new_donations().await {
-                error!("Error while polling new donations: {}", err);
-            }
-
-            info!(
-                "Polling for new donations done, next poll at {}",
-                get_time_after_duration(donation_interval)
-            );
-            sleep(Duration::from_millis(donation_interval)).await;
-        }
+        info!(
+            "Polling {} G-Portal account(s): {}",
+            accounts.len(),
+            accounts.iter().map(|account| account.name.as_str()).collect::<Vec<_>>().join(", ")
+        );
+        futures::future::join_all(
+            accounts
+                .into_iter()
+                .map(|account| account.run(donation_interval, backfill_since, shutdown.clone())),
+        )
+        .await;
     }
     else {
-        info!("Skipping donation fetching because donation webhook is empty. Please add the Discord webhook in the 'DISCORD_DONATION_WEBHOOK' environment variable if you want to get notified from new donations.")
+        info!("Skipping donation fetching because no notifiers are configured. Please add the Discord webhook in the 'DISCORD_DONATION_WEBHOOK' environment variable or notifiers in the config file if you want to get notified from new donations.")
     }
 }
 
+/// Resolves on Ctrl+C, or SIGTERM e.g. from `docker stop`
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => (),
+            _ = terminate.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_get_positional_args() {
+        let before = args(&["gportal-integrations", "--account", "main", "history", "14500001"]);
+        assert_eq!(get_positional_args(&before), vec!["history", "14500001"]);
+
+        let after = args(&["gportal-integrations", "history", "14500001", "--account", "main"]);
+        assert_eq!(get_positional_args(&after), vec!["history", "14500001"]);
+
+        let between = args(&["gportal-integrations", "history", "--account=main", "14500001"]);
+        assert_eq!(get_positional_args(&between), vec!["history", "14500001"]);
+
+        let no_id = args(&["gportal-integrations", "history", "--account", "14500001"]);
+        assert_eq!(get_positional_args(&no_id), vec!["history"]);
+    }
+
     #[tokio::test]
     async fn test_get_totp_secret() {
         let totp_secret = dotenv::var("TOTP_SECRET").unwrap_or("".to_string());
@@ -105,7 +234,7 @@ mod tests {
         let refresh_token = token.as_ref().map(|res| res.refresh_token.clone());
 
         println!("Access token: {}", access_token.unwrap());
-        println!("");
+        println!();
         println!("Refresh token: {}", refresh_token.unwrap());
     }
 
@@ -124,7 +253,7 @@ mod tests {
         let refresh_token = token.as_ref().map(|res| res.refresh_token.clone());
 
         println!("Access token: {}", access_token.unwrap());
-        println!("");
+        println!();
         println!("Refresh token: {}", refresh_token.unwrap());
     }
 }
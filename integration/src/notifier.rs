@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+
+use api::{Donor, PricingPolicy, PurposeParser, Transaction};
+use async_trait::async_trait;
+use chrono::Duration;
+use handlebars::Handlebars;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::discord::EmbedTemplate;
+
+mod discord;
+mod matrix;
+mod slack;
+mod telegram;
+mod webhook;
+
+pub use discord::{DiscordConfig, DiscordNotifier};
+pub use matrix::{MatrixConfig, MatrixNotifier};
+pub use slack::{SlackConfig, SlackNotifier};
+pub use telegram::{TelegramConfig, TelegramNotifier};
+pub use webhook::{WebhookConfig, WebhookNotifier};
+
+/// Message used by the text based sinks when no template is configured
+const DEFAULT_TEXT: &str = "New donation from {{donor}}: {{amount}} ({{days}} days)\n{{purpose}}";
+
+/// Somewhere donations are announced
+#[async_trait]
+pub trait Notifier: Send {
+    /// Sink name the notification status and retries are tracked under
+    fn name(&self) -> &str;
+
+    /// Sends a single donation
+    async fn send(&mut self, notification: &Notification) -> Result<(), anyhow::Error>;
+
+    /// Sends the donations, returning the result of each in the same order. One by one unless the sink batches them.
+    async fn notify(&mut self, notifications: &[Notification]) -> Vec<Result<(), anyhow::Error>> {
+        let mut results = Vec::with_capacity(notifications.len());
+        for notification in notifications {
+            results.push(self.send(notification).await);
+        }
+
+        results
+    }
+}
+
+/// Donation to announce
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub donation: DonationContext,
+    /// Named template picked by the routes, the sink's own template is used when not set
+    pub template: Option<String>,
+}
+
+/// Entry of the `[templates]` section, routes refer to these by name.
+/// Discord sinks use the `embed`, the text based sinks the `text`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NamedTemplate {
+    pub embed: Option<EmbedTemplate>,
+    pub text: Option<String>,
+}
+
+pub type Templates = HashMap<String, NamedTemplate>;
+
+/// Everything a donation template can refer to, e.g. `{{donor}}` or `{{soldier_name}}`
+#[derive(Debug, Clone, Serialize)]
+pub struct DonationContext {
+    pub id: String,
+    /// Public name of the donor, email addresses are masked
+    pub donor: String,
+    /// Unmasked donor, left out of the templates so emails don't end up in public channels
+    #[serde(skip)]
+    pub donor_account: Donor,
+    pub purpose: String,
+    /// Formatted amount with the currency, e.g. `10.00 €`
+    pub amount: String,
+    pub amount_value: Decimal,
+    pub currency: String,
+    pub days: i64,
+    /// Donation time as an ISO 8601 timestamp in UTC
+    pub time: String,
+    pub timestamp: i64,
+    /// End of the VIP period as a unix timestamp, handy for Discord's `<t:...:R>` formatting
+    pub end_timestamp: i64,
+    pub end_date: String,
+    pub soldier_name: Option<String>,
+    pub discord_tag: Option<String>,
+    pub discord_id: Option<String>,
+    pub steam_id: Option<String>,
+    pub ea_id: Option<String>,
+    pub server_name: Option<String>,
+    pub vip_management_url: Option<String>,
+}
+
+impl DonationContext {
+    pub fn new(transaction: &Transaction, pricing: &PricingPolicy, purpose_parser: &PurposeParser) -> Self {
+        let (donor, purpose_text) = transaction.get_donator_and_purpose();
+        let purpose = transaction.purpose(purpose_parser);
+        let days = transaction.amount_to_days(pricing);
+        let donation_day = transaction.time_to_utc();
+        let end_date = donation_day + Duration::days(days);
+
+        DonationContext {
+            id: transaction.id.clone(),
+            donor: donor.public_name(),
+            donor_account: donor,
+            purpose: purpose_text,
+            amount: transaction.amount.to_string(),
+            amount_value: transaction.amount.amount,
+            currency: transaction.amount.currency.clone(),
+            days,
+            time: donation_day.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            timestamp: donation_day.timestamp(),
+            end_timestamp: end_date.timestamp(),
+            end_date: end_date.format("%Y-%m-%d").to_string(),
+            soldier_name: purpose.soldier_name,
+            discord_tag: purpose.discord_tag,
+            discord_id: purpose.discord_id,
+            steam_id: purpose.steam_id,
+            ea_id: purpose.ea_id,
+            server_name: purpose.server_name,
+            vip_management_url: dotenv::var("VIP_MANAGEMENT_URL").ok(),
+        }
+    }
+}
+
+/// Renders a Handlebars template from the donation, without HTML escaping
+pub fn render_template(template: &str, context: &DonationContext) -> Result<String, anyhow::Error> {
+    render_template_escaped(template, context, handlebars::no_escape)
+}
+
+/// Renders the template with every substituted value passed through `escape`, the template's own text is kept as is
+fn render_template_escaped(
+    template: &str,
+    context: &DonationContext,
+    escape: fn(&str) -> String,
+) -> Result<String, anyhow::Error> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(escape);
+
+    Ok(handlebars.render_template(template, context)?.trim().to_string())
+}
+
+/// Renders the text of the named template, falling back to the sink's own template and then the default text
+fn render_text(
+    notification: &Notification,
+    templates: &Templates,
+    template: Option<&str>,
+) -> Result<String, anyhow::Error> {
+    render_text_escaped(notification, templates, template, handlebars::no_escape)
+}
+
+/// Same as [`render_text`], for sinks whose markup donors could abuse
+fn render_text_escaped(
+    notification: &Notification,
+    templates: &Templates,
+    template: Option<&str>,
+    escape: fn(&str) -> String,
+) -> Result<String, anyhow::Error> {
+    let named = notification
+        .template
+        .as_ref()
+        .and_then(|name| templates.get(name))
+        .and_then(|named| named.text.as_deref());
+
+    render_template_escaped(named.or(template).unwrap_or(DEFAULT_TEXT), &notification.donation, escape)
+}
+
+/// Turns a non-successful response into an error including the body
+async fn check_response(res: reqwest::Response, sink: &str) -> Result<(), anyhow::Error> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let body = res.text().await.unwrap_or_default();
+    Err(anyhow::anyhow!("{} responded with {}: {}", sink, status, body))
+}
+
+/// Entry of the `[[notifiers]]` list in the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifierConfig {
+    Discord(DiscordConfig),
+    Webhook(WebhookConfig),
+    Slack(SlackConfig),
+    Matrix(MatrixConfig),
+    Telegram(TelegramConfig),
+}
+
+impl NotifierConfig {
+    /// `default_template` is used by Discord sinks without their own template
+    pub fn build(&self, default_template: &EmbedTemplate, templates: &Templates) -> Box<dyn Notifier> {
+        let templates = templates.clone();
+
+        match self {
+            NotifierConfig::Discord(config) => Box::new(DiscordNotifier::new(config, default_template, templates)),
+            NotifierConfig::Webhook(config) => Box::new(WebhookNotifier::new(config)),
+            NotifierConfig::Slack(config) => Box::new(SlackNotifier::new(config, templates)),
+            NotifierConfig::Matrix(config) => Box::new(MatrixNotifier::new(config, templates)),
+            NotifierConfig::Telegram(config) => Box::new(TelegramNotifier::new(config, templates)),
+        }
+    }
+}
+
+/// Builds the configured notifiers, only the ones named in `only` unless it's empty.
+/// `DISCORD_DONATION_WEBHOOK` is used as the only sink when no list is configured.
+pub fn build_notifiers(
+    configs: &[NotifierConfig],
+    default_template: &EmbedTemplate,
+    templates: &Templates,
+    discord_webhook: Option<String>,
+    only: &[String],
+) -> Result<Vec<Box<dyn Notifier>>, anyhow::Error> {
+    let mut configs = configs.to_vec();
+    if configs.is_empty() {
+        if let Some(webhook_url) = discord_webhook.filter(|url| !url.is_empty()) {
+            configs.push(NotifierConfig::Discord(DiscordConfig {
+                name: None,
+                webhook_url,
+                template: None,
+            }));
+        }
+    }
+
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    for config in &configs {
+        let notifier = config.build(default_template, templates);
+        if notifiers.iter().any(|existing| existing.name() == notifier.name()) {
+            return Err(anyhow::anyhow!(
+                "Notifier {} is configured twice, give them unique names",
+                notifier.name()
+            ));
+        }
+        notifiers.push(notifier);
+    }
+
+    if let Some(unknown) = only.iter().find(|name| !notifiers.iter().any(|notifier| notifier.name() == *name)) {
+        return Err(anyhow::anyhow!("Unknown notifier {}", unknown));
+    }
+    notifiers.retain(|notifier| only.is_empty() || only.iter().any(|name| name == notifier.name()));
+
+    for notifier in &notifiers {
+        info!("Announcing donations to {}", notifier.name());
+    }
+
+    Ok(notifiers)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use api::Region;
+
+    pub fn notification(template: Option<&str>) -> Notification {
+        Notification {
+            donation: context(),
+            template: template.map(str::to_string),
+        }
+    }
+
+    pub fn context() -> DonationContext {
+        donation_context("Donation from poorGuy - Purpose: soldiername: PoorGuy", "10.00 €")
+    }
+
+    /// Donation 14500000 with the given description and amount
+    pub fn donation_context(description: &str, amount: &str) -> DonationContext {
+        let transaction = Transaction::from_row(
+            &["14500000", description, amount, "2022-10-01T21:50:01+02:00"],
+            Region::Eur,
+        )
+        .unwrap();
+
+        DonationContext::new(&transaction, &PricingPolicy::default(), &PurposeParser::default())
+    }
+
+    #[test]
+    fn test_parse_notifiers() {
+        let notifiers: Vec<NotifierConfig> = toml::from_str::<toml::Value>(
+            r#"
+            [[notifiers]]
+            type = "discord"
+            webhook_url = "https://discord.com/api/webhooks/1/abc"
+
+            [[notifiers]]
+            type = "telegram"
+            name = "telegram-admins"
+            bot_token = "123:abc"
+            chat_id = "-100123"
+            "#,
+        )
+        .unwrap()["notifiers"]
+            .clone()
+            .try_into()
+            .unwrap();
+
+        let names: Vec<String> = notifiers
+            .iter()
+            .map(|config| config.build(&EmbedTemplate::default(), &Templates::new()).name().to_string())
+            .collect();
+        assert_eq!(names, vec!["discord", "telegram-admins"]);
+
+        let duplicates = [notifiers[0].clone(), notifiers[0].clone()];
+        assert!(build_notifiers(&duplicates, &EmbedTemplate::default(), &Templates::new(), None, &[]).is_err());
+
+        let only = ["telegram-admins".to_string()];
+        let selected = build_notifiers(&notifiers, &EmbedTemplate::default(), &Templates::new(), None, &only).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name(), "telegram-admins");
+        let unknown = ["slack".to_string()];
+        assert!(build_notifiers(&notifiers, &EmbedTemplate::default(), &Templates::new(), None, &unknown).is_err());
+    }
+
+    #[test]
+    fn test_discord_webhook_fallback() {
+        let template = EmbedTemplate::default();
+
+        let templates = Templates::new();
+
+        let webhook = Some("https://discord.com/api/webhooks/1/abc".to_string());
+        let notifiers = build_notifiers(&[], &template, &templates, webhook, &[]).unwrap();
+        assert_eq!(notifiers[0].name(), "discord");
+        assert!(build_notifiers(&[], &template, &templates, Some("".to_string()), &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_render_text() {
+        let mut templates = Templates::new();
+        templates.insert(
+            "short".to_string(),
+            NamedTemplate {
+                embed: None,
+                text: Some("{{amount}}".to_string()),
+            },
+        );
+
+        assert_eq!(
+            render_text(&notification(None), &templates, None).unwrap(),
+            "New donation from poorGuy: 10.00 € (90 days)\nsoldiername: PoorGuy"
+        );
+        assert_eq!(render_text(&notification(None), &templates, Some("{{soldier_name}}")).unwrap(), "PoorGuy");
+        assert_eq!(render_text(&notification(Some("short")), &templates, Some("{{soldier_name}}")).unwrap(), "10.00 €");
+        assert_eq!(render_text(&notification(Some("missing")), &templates, None).unwrap().lines().count(), 2);
+    }
+}
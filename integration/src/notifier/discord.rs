@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{Notification, Notifier, Templates};
+use crate::discord::{DiscordWebhookClient, EmbedTemplate};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscordConfig {
+    /// Defaults to `discord`
+    pub name: Option<String>,
+    pub webhook_url: String,
+    /// Defaults to the `[discord]` section
+    pub template: Option<EmbedTemplate>,
+}
+
+/// Announces donations as embeds through a Discord webhook
+pub struct DiscordNotifier {
+    name: String,
+    client: DiscordWebhookClient,
+    template: EmbedTemplate,
+    templates: Templates,
+}
+
+impl DiscordNotifier {
+    pub fn new(config: &DiscordConfig, default_template: &EmbedTemplate, templates: Templates) -> Self {
+        let template = config.template.clone().unwrap_or_else(|| default_template.clone());
+
+        DiscordNotifier {
+            name: config.name.clone().unwrap_or_else(|| "discord".to_string()),
+            client: DiscordWebhookClient::new(&config.webhook_url)
+                .with_username(template.username())
+                .with_avatar_url(template.avatar_url()),
+            template,
+            templates,
+        }
+    }
+
+    /// Embed template of the named template, falling back to the sink's own template
+    fn template(&self, name: Option<&str>) -> &EmbedTemplate {
+        name.and_then(|name| self.templates.get(name))
+            .and_then(|named| named.embed.as_ref())
+            .unwrap_or(&self.template)
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&mut self, notification: &Notification) -> Result<(), anyhow::Error> {
+        self.notify(std::slice::from_ref(notification)).await.remove(0)
+    }
+
+    /// Batches the embeds into as few messages as possible
+    async fn notify(&mut self, notifications: &[Notification]) -> Vec<Result<(), anyhow::Error>> {
+        let mut results: Vec<Option<Result<(), anyhow::Error>>> = Vec::with_capacity(notifications.len());
+        for (i, notification) in notifications.iter().enumerate() {
+            let embed = self
+                .template(notification.template.as_deref())
+                .render(&notification.donation);
+            match embed {
+                Ok(embed) => {
+                    self.client.queue(&i.to_string(), embed);
+                    results.push(None);
+                }
+                Err(err) => results.push(Some(Err(err.context("Failed to render the donation embed")))),
+            }
+        }
+
+        for (i, result) in self.client.flush().await {
+            if let Ok(i) = i.parse::<usize>() {
+                results[i] = Some(result);
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(anyhow::anyhow!("Donation embed was not sent"))))
+            .collect()
+    }
+}
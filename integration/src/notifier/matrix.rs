@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{check_response, render_text, Notification, Notifier, Templates};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixConfig {
+    /// Defaults to `matrix`
+    pub name: Option<String>,
+    /// e.g. `https://matrix.org`
+    pub homeserver: String,
+    /// e.g. `!abcdefg:matrix.org`
+    pub room_id: String,
+    pub access_token: String,
+    /// Handlebars template of the message
+    pub template: Option<String>,
+}
+
+/// Sends donations as text messages to a Matrix room
+pub struct MatrixNotifier {
+    name: String,
+    client: reqwest::Client,
+    homeserver: String,
+    room_id: String,
+    access_token: String,
+    template: Option<String>,
+    templates: Templates,
+}
+
+impl MatrixNotifier {
+    pub fn new(config: &MatrixConfig, templates: Templates) -> Self {
+        MatrixNotifier {
+            name: config.name.clone().unwrap_or_else(|| "matrix".to_string()),
+            client: reqwest::Client::new(),
+            homeserver: config.homeserver.trim_end_matches('/').to_string(),
+            room_id: config.room_id.clone(),
+            access_token: config.access_token.clone(),
+            template: config.template.clone(),
+            templates,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&mut self, notification: &Notification) -> Result<(), anyhow::Error> {
+        let text = render_text(notification, &self.templates, self.template.as_deref())?;
+
+        // Using the transaction ID as the Matrix transaction ID makes retries idempotent
+        let mut url = reqwest::Url::parse(&self.homeserver)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Matrix homeserver {}", self.homeserver))?
+            .extend(&["_matrix", "client", "v3", "rooms", &self.room_id, "send", "m.room.message"])
+            .push(&format!("gportal-{}", notification.donation.id));
+
+        let res = self
+            .client
+            .put(url)
+            .bearer_auth(&self.access_token)
+            .json(&json!({ "msgtype": "m.text", "body": text }))
+            .send()
+            .await?;
+
+        check_response(res, &self.name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::tests::notification;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_matrix_message() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("PUT"))
+            .and(path("/_matrix/client/v3/rooms/!room:matrix.org/send/m.room.message/gportal-14500000"))
+            .and(header("Authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{ "event_id": "$1" }"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut notifier = MatrixNotifier::new(&MatrixConfig {
+            name: None,
+            homeserver: server.uri(),
+            room_id: "!room:matrix.org".to_string(),
+            access_token: "token".to_string(),
+            template: None,
+        }, Templates::new());
+        assert!(notifier.notify(&[notification(None)]).await[0].is_ok());
+    }
+}
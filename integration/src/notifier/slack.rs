@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{check_response, render_text_escaped, Notification, Notifier, Templates};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlackConfig {
+    /// Defaults to `slack`
+    pub name: Option<String>,
+    pub webhook_url: String,
+    /// Handlebars template of the message
+    pub template: Option<String>,
+}
+
+/// Posts donations to a Slack incoming webhook
+pub struct SlackNotifier {
+    name: String,
+    client: reqwest::Client,
+    webhook_url: String,
+    template: Option<String>,
+    templates: Templates,
+}
+
+impl SlackNotifier {
+    pub fn new(config: &SlackConfig, templates: Templates) -> Self {
+        SlackNotifier {
+            name: config.name.clone().unwrap_or_else(|| "slack".to_string()),
+            client: reqwest::Client::new(),
+            webhook_url: config.webhook_url.clone(),
+            template: config.template.clone(),
+            templates,
+        }
+    }
+}
+
+/// Escapes Slack's control characters in the donor's texts, so a purpose can't ping with `<!channel>`
+/// or post a disguised `<url|text>` link
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&mut self, notification: &Notification) -> Result<(), anyhow::Error> {
+        let text = render_text_escaped(notification, &self.templates, self.template.as_deref(), escape)?;
+        let res = self
+            .client
+            .post(&self.webhook_url)
+            .json(&json!({ "text": text }))
+            .send()
+            .await
+            // The webhook URL is a secret, keep it out of the logs and the outbox
+            .map_err(reqwest::Error::without_url)?;
+
+        check_response(res, &self.name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::tests::{donation_context, notification};
+    use wiremock::matchers::{body_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_slack_message() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(body_json(json!({ "text": "poorGuy donated 10.00 €" })))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut notifier = SlackNotifier::new(&SlackConfig {
+            name: None,
+            webhook_url: server.uri(),
+            template: Some("{{donor}} donated {{amount}}".to_string()),
+        }, Templates::new());
+        assert!(notifier.notify(&[notification(None)]).await[0].is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_slack_escapes_donor_text() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(body_json(json!({ "text": "<!here> &lt;!channel&gt; &amp; &lt;https://evil|G-Portal&gt;" })))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut notifier = SlackNotifier::new(&SlackConfig {
+            name: None,
+            webhook_url: server.uri(),
+            template: Some("<!here> {{purpose}}".to_string()),
+        }, Templates::new());
+        let notification = Notification {
+            donation: donation_context(
+                "Donation from poorGuy - Purpose: <!channel> & <https://evil|G-Portal>",
+                "10.00 €",
+            ),
+            template: None,
+        };
+        assert!(notifier.notify(&[notification]).await[0].is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{check_response, render_text, Notification, Notifier, Templates};
+
+const TELEGRAM_API_URL: &str = r#"https://api.telegram.org"#;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegramConfig {
+    /// Defaults to `telegram`
+    pub name: Option<String>,
+    pub bot_token: String,
+    /// Chat ID or `@channelusername`
+    pub chat_id: String,
+    /// Handlebars template of the message
+    pub template: Option<String>,
+    /// Defaults to `https://api.telegram.org`
+    pub api_url: Option<String>,
+}
+
+/// Sends donations to a Telegram chat through the bot API
+pub struct TelegramNotifier {
+    name: String,
+    client: reqwest::Client,
+    api_url: String,
+    bot_token: String,
+    chat_id: String,
+    template: Option<String>,
+    templates: Templates,
+}
+
+impl TelegramNotifier {
+    pub fn new(config: &TelegramConfig, templates: Templates) -> Self {
+        TelegramNotifier {
+            name: config.name.clone().unwrap_or_else(|| "telegram".to_string()),
+            client: reqwest::Client::new(),
+            api_url: config
+                .api_url
+                .as_deref()
+                .unwrap_or(TELEGRAM_API_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            bot_token: config.bot_token.clone(),
+            chat_id: config.chat_id.clone(),
+            template: config.template.clone(),
+            templates,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&mut self, notification: &Notification) -> Result<(), anyhow::Error> {
+        let text = render_text(notification, &self.templates, self.template.as_deref())?;
+        let res = self
+            .client
+            .post(format!("{}/bot{}/sendMessage", self.api_url, self.bot_token))
+            .json(&json!({ "chat_id": self.chat_id, "text": text }))
+            .send()
+            .await
+            // The URL contains the bot token, keep it out of the logs and the outbox
+            .map_err(reqwest::Error::without_url)?;
+
+        check_response(res, &self.name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::tests::notification;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_telegram_message() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/bot123:abc/sendMessage"))
+            .respond_with(ResponseTemplate::new(403).set_body_string(r#"{ "ok": false }"#))
+            .mount(&server)
+            .await;
+
+        let mut notifier = TelegramNotifier::new(&TelegramConfig {
+            name: None,
+            bot_token: "123:abc".to_string(),
+            chat_id: "-100123".to_string(),
+            template: None,
+            api_url: Some(server.uri()),
+        }, Templates::new());
+
+        let results = notifier.notify(&[notification(None)]).await;
+        assert!(results[0].as_ref().unwrap_err().to_string().contains("403"));
+    }
+
+    #[tokio::test]
+    async fn test_error_hides_bot_token() {
+        let mut notifier = TelegramNotifier::new(&TelegramConfig {
+            name: None,
+            bot_token: "123:abc".to_string(),
+            chat_id: "-100123".to_string(),
+            template: None,
+            api_url: Some("http://127.0.0.1:1".to_string()),
+        }, Templates::new());
+
+        let results = notifier.notify(&[notification(None)]).await;
+        assert!(!results[0].as_ref().unwrap_err().to_string().contains("123:abc"));
+    }
+}
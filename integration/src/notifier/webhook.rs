@@ -0,0 +1,190 @@
+use api::Donor;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::{check_response, DonationContext, Notification, Notifier};
+
+pub const SIGNATURE_HEADER: &str = r#"X-Signature-256"#;
+pub const IDEMPOTENCY_KEY_HEADER: &str = r#"Idempotency-Key"#;
+
+/// Bumped on breaking changes to [`DonationEvent`]
+pub const EVENT_VERSION: u32 = 1;
+
+/// Body of the webhook, versioned so receivers can tell the formats apart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DonationEvent {
+    pub version: u32,
+    /// Always `donation`
+    pub event: String,
+    /// G-Portal transaction ID, also sent as the `Idempotency-Key` header
+    pub id: String,
+    /// Unmasked donor, `{ "type": "username" | "email", "value": "..." }`
+    pub donor: Donor,
+    pub purpose: EventPurpose,
+    pub amount: EventAmount,
+    /// VIP days the amount is worth
+    pub days: i64,
+    /// Donation time, ISO 8601 in UTC
+    pub time: String,
+    /// End of the VIP period, ISO 8601 in UTC
+    pub ends_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventPurpose {
+    pub text: String,
+    pub soldier_name: Option<String>,
+    pub discord_tag: Option<String>,
+    pub discord_id: Option<String>,
+    pub steam_id: Option<String>,
+    pub ea_id: Option<String>,
+    pub server_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventAmount {
+    /// Decimal as a string, e.g. `"10.00"`
+    pub value: Decimal,
+    pub currency: String,
+}
+
+impl DonationEvent {
+    pub fn new(donation: &DonationContext) -> Self {
+        DonationEvent {
+            version: EVENT_VERSION,
+            event: "donation".to_string(),
+            id: donation.id.clone(),
+            donor: donation.donor_account.clone(),
+            purpose: EventPurpose {
+                text: donation.purpose.clone(),
+                soldier_name: donation.soldier_name.clone(),
+                discord_tag: donation.discord_tag.clone(),
+                discord_id: donation.discord_id.clone(),
+                steam_id: donation.steam_id.clone(),
+                ea_id: donation.ea_id.clone(),
+                server_name: donation.server_name.clone(),
+            },
+            amount: EventAmount {
+                value: donation.amount_value,
+                currency: donation.currency.clone(),
+            },
+            days: donation.days,
+            time: donation.time.clone(),
+            ends_at: Utc
+                .timestamp_opt(donation.end_timestamp, 0)
+                .single()
+                .map(|ends_at| ends_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    /// Defaults to `webhook`
+    pub name: Option<String>,
+    pub url: String,
+    /// Signs the body with HMAC-SHA256 when set
+    pub secret: Option<String>,
+}
+
+/// POSTs every donation as a signed [`DonationEvent`] to an HTTP endpoint, e.g. our own backend.
+/// Failed deliveries are retried through the outbox with the same idempotency key.
+pub struct WebhookNotifier {
+    name: String,
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &WebhookConfig) -> Self {
+        WebhookNotifier {
+            name: config.name.clone().unwrap_or_else(|| "webhook".to_string()),
+            client: reqwest::Client::new(),
+            url: config.url.clone(),
+            secret: config.secret.clone(),
+        }
+    }
+}
+
+/// `sha256=<hex HMAC-SHA256 of the body>`, the receiver computes the same with the shared secret
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&mut self, notification: &Notification) -> Result<(), anyhow::Error> {
+        let donation = &notification.donation;
+        let body = serde_json::to_string(&DonationEvent::new(donation))?;
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header(IDEMPOTENCY_KEY_HEADER, &donation.id);
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body.as_bytes()));
+        }
+
+        check_response(request.body(body).send().await?, &self.name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::tests::notification;
+    use wiremock::matchers::{header, header_exists, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_signed_webhook() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(header_exists(SIGNATURE_HEADER))
+            .and(header(IDEMPOTENCY_KEY_HEADER, "14500000"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut notifier = WebhookNotifier::new(&WebhookConfig {
+            name: None,
+            url: server.uri(),
+            secret: Some("secret".to_string()),
+        });
+        let results = notifier.notify(&[notification(None)]).await;
+        assert!(results[0].is_ok());
+
+        let request = &server.received_requests().await.unwrap()[0];
+        let signature = request.headers.get(&SIGNATURE_HEADER.parse().unwrap()).unwrap().last().as_str();
+        assert_eq!(signature, sign("secret", &request.body));
+
+        let event: DonationEvent = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(event.version, EVENT_VERSION);
+        assert_eq!(event.id, "14500000");
+        assert_eq!(event.donor, Donor::Username("poorGuy".to_string()));
+        assert_eq!(event.purpose.soldier_name.as_deref(), Some("PoorGuy"));
+        assert_eq!(event.amount.value.to_string(), "10.00");
+        assert_eq!(event.days, 90);
+        assert_eq!(event.time, "2022-10-01T19:50:01.000Z");
+        assert_eq!(event.ends_at, "2022-12-30T19:50:01.000Z");
+
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["amount"]["value"], "10.00");
+    }
+}
@@ -1,4 +1,6 @@
-use reqwest::header::{HeaderValue, CONTENT_TYPE, USER_AGENT};
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, DATE};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -8,23 +10,378 @@ pub struct Token {
     pub refresh_expires_in: i64,
     pub refresh_token: String,
     pub token_type: String,
+    /// Only issued with the `openid` scope
+    #[serde(default)]
     pub id_token: String,
-    #[serde(alias = "not-before-policy")]
+    #[serde(default, alias = "not-before-policy")]
     pub not_before_policy: i64,
+    #[serde(default)]
     pub session_state: String,
+    #[serde(default)]
     pub scope: String,
 }
 
+/// OAuth 2.0 error response of the Keycloak endpoints
+#[derive(Debug, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    #[serde(default)]
+    pub error_description: String,
+}
+
+/// Why a request to the OpenID provider failed
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Token request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("OpenID discovery failed: {0}")]
+    Discovery(String),
+
+    /// The refresh token was revoked, or the session ended e.g. because Keycloak was restarted
+    #[error("Session is no longer valid: {0}")]
+    SessionExpired(String),
+
+    /// Keycloak's direct grant answers a wrong TOTP code like this too, `server_time` as for `InvalidTotp`
+    #[error("Invalid username or password: {description}")]
+    InvalidCredentials {
+        description: String,
+        server_time: Option<DateTime<Utc>>,
+    },
+
+    /// `server_time` is the `Date` of the response, for detecting clock drift
+    #[error("Invalid TOTP code: {description}")]
+    InvalidTotp {
+        description: String,
+        server_time: Option<DateTime<Utc>>,
+    },
+
+    /// Too many failed logins or the account was disabled
+    #[error("Account is locked: {0}")]
+    AccountLocked(String),
+
+    #[error("OpenID provider responded with {status}: {error} {description}")]
+    Rejected {
+        status: StatusCode,
+        error: String,
+        description: String,
+    },
+}
+
+impl AuthError {
+    fn classify(status: StatusCode, headers: &HeaderMap, body: &str) -> AuthError {
+        let response: ErrorResponse = match serde_json::from_str(body) {
+            Ok(response) => response,
+            Err(_) => {
+                return AuthError::Rejected {
+                    status,
+                    error: String::new(),
+                    description: body.to_string(),
+                }
+            }
+        };
+
+        let description = response.error_description.to_lowercase();
+        match response.error.as_str() {
+            _ if response.error == "invalid_totp" || description.contains("otp") => AuthError::InvalidTotp {
+                description: response.error_description,
+                server_time: server_time(headers),
+            },
+            _ if description.contains("disabled") || description.contains("locked") => {
+                AuthError::AccountLocked(response.error_description)
+            }
+            "invalid_grant" if description.contains("token") || description.contains("session") => {
+                AuthError::SessionExpired(response.error_description)
+            }
+            "invalid_grant" => AuthError::InvalidCredentials {
+                description: response.error_description,
+                server_time: server_time(headers),
+            },
+            _ => AuthError::Rejected {
+                status,
+                error: response.error,
+                description: response.error_description,
+            },
+        }
+    }
+
+    /// Whether Keycloak refused the credentials, as opposed to not being reachable or failing itself,
+    /// e.g. a 502 from its proxy while it restarts
+    pub fn is_rejected(&self) -> bool {
+        match self {
+            AuthError::Http(_) | AuthError::Discovery(_) => false,
+            AuthError::Rejected { status, .. } => !status.is_server_error(),
+            _ => true,
+        }
+    }
+}
+
+fn server_time(headers: &HeaderMap) -> Option<DateTime<Utc>> {
+    let date = headers.get(DATE)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(date).ok().map(|time| time.with_timezone(&Utc))
+}
+
 const USER_AGENT_STR: &str = r#"Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:82.0) Gecko/20100101 Firefox/82.0"#;
 
-pub async fn get_token(path: &str, payload: serde_json::Value) -> Result<Token, reqwest::Error> {
-    let client = reqwest::Client::new();
-    let k_res = client
-        .post(path)
-        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
-        .header(USER_AGENT, USER_AGENT_STR)
-        .form(&payload)
-        .send()
-        .await?.error_for_status()?;
-    k_res.json().await
+/// The parts of the provider's `.well-known/openid-configuration` the client uses
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub revocation_endpoint: Option<String>,
+    pub end_session_endpoint: Option<String>,
+}
+
+/// OpenID Connect client of a public client, with the endpoints looked up from the provider's discovery document
+pub struct OidcClient {
+    client: reqwest::Client,
+    client_id: String,
+    metadata: ProviderMetadata,
+}
+
+impl OidcClient {
+    /// Fetches the discovery document of `issuer`, e.g. `https://auth.g-portal.com/auth/realms/master`
+    pub async fn discover(issuer: &str, client_id: &str) -> Result<OidcClient, AuthError> {
+        let issuer = issuer.trim_end_matches('/');
+        let client = reqwest::Client::builder().user_agent(USER_AGENT_STR).build()?;
+
+        let metadata: ProviderMetadata = client
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(AuthError::Discovery(format!(
+                "Provider claims to be {} instead of {}",
+                metadata.issuer, issuer
+            )));
+        }
+
+        debug!("Discovered the OpenID provider {}", metadata.issuer);
+        Ok(OidcClient {
+            client,
+            client_id: client_id.to_string(),
+            metadata,
+        })
+    }
+
+    pub fn metadata(&self) -> &ProviderMetadata {
+        &self.metadata
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Resource owner password grant, `totp` is Keycloak's extra parameter for the one-time code
+    pub async fn password_grant(&self, username: &str, password: &str, totp: &str, scope: &str) -> Result<Token, AuthError> {
+        self.token_request(&[
+            ("grant_type", "password"),
+            ("client_id", &self.client_id),
+            ("scope", scope),
+            ("username", username),
+            ("password", password),
+            ("totp", totp),
+        ])
+        .await
+    }
+
+    pub async fn refresh_grant(&self, refresh_token: &str) -> Result<Token, AuthError> {
+        self.token_request(&[
+            ("grant_type", "refresh_token"),
+            ("client_id", &self.client_id),
+            ("refresh_token", refresh_token),
+        ])
+        .await
+    }
+
+    /// Ends the session of the refresh token, with the revocation endpoint or the logout endpoint if there is none
+    pub async fn revoke(&self, refresh_token: &str) -> Result<(), AuthError> {
+        if let Some(endpoint) = &self.metadata.revocation_endpoint {
+            let form = [
+                ("client_id", self.client_id.as_str()),
+                ("token", refresh_token),
+                ("token_type_hint", "refresh_token"),
+            ];
+            self.post_form(endpoint, &form).await?;
+        } else if let Some(endpoint) = &self.metadata.end_session_endpoint {
+            let form = [("client_id", self.client_id.as_str()), ("refresh_token", refresh_token)];
+            self.post_form(endpoint, &form).await?;
+        } else {
+            return Err(AuthError::Discovery("Provider has no revocation or logout endpoint".to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn token_request(&self, form: &[(&str, &str)]) -> Result<Token, AuthError> {
+        Ok(self.post_form(&self.metadata.token_endpoint, form).await?.json().await?)
+    }
+
+    /// Form POST, error responses are turned into [`AuthError`]s
+    async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> Result<reqwest::Response, AuthError> {
+        let res = self.client.post(url).form(form).send().await?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let headers = res.headers().clone();
+            let body = res.text().await?;
+            return Err(AuthError::classify(status, &headers, &body));
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Token response of Keycloak for a login with the `openid` scope
+    pub(crate) fn token_response(access_token: &str, id_token: &str) -> serde_json::Value {
+        json!({
+            "access_token": access_token, "expires_in": 300, "refresh_expires_in": 1800,
+            "refresh_token": "refresh", "token_type": "Bearer", "id_token": id_token,
+            "not-before-policy": 0, "session_state": "state", "scope": "openid gportal"
+        })
+    }
+
+    /// Serves a discovery document pointing every endpoint to the mock server
+    pub(crate) async fn mount_discovery(server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": server.uri(),
+                "token_endpoint": format!("{}/token", server.uri()),
+                "jwks_uri": format!("{}/certs", server.uri()),
+                "revocation_endpoint": format!("{}/revoke", server.uri()),
+                "end_session_endpoint": format!("{}/logout", server.uri()),
+            })))
+            .mount(server)
+            .await;
+    }
+
+    fn classify(body: &str) -> AuthError {
+        AuthError::classify(StatusCode::BAD_REQUEST, &HeaderMap::new(), body)
+    }
+
+    #[test]
+    fn test_classify_errors() {
+        assert!(matches!(
+            classify(r#"{"error":"invalid_grant","error_description":"Token is not active"}"#),
+            AuthError::SessionExpired(_)
+        ));
+        assert!(matches!(
+            classify(r#"{"error":"invalid_grant","error_description":"Session not active"}"#),
+            AuthError::SessionExpired(_)
+        ));
+        assert!(matches!(
+            classify(r#"{"error":"invalid_grant","error_description":"Invalid user credentials"}"#),
+            AuthError::InvalidCredentials { .. }
+        ));
+        assert!(matches!(
+            classify(r#"{"error":"invalid_totp","error_description":"Invalid TOTP"}"#),
+            AuthError::InvalidTotp { .. }
+        ));
+        assert!(matches!(
+            classify(r#"{"error":"invalid_grant","error_description":"Account temporarily disabled"}"#),
+            AuthError::AccountLocked(_)
+        ));
+        assert!(matches!(
+            classify(r#"{"error":"unauthorized_client","error_description":"Invalid client"}"#),
+            AuthError::Rejected { .. }
+        ));
+        assert!(matches!(classify("Bad Request"), AuthError::Rejected { .. }));
+        assert!(classify("Bad Request").is_rejected());
+
+        let unavailable = AuthError::classify(StatusCode::BAD_GATEWAY, &HeaderMap::new(), "<html>Bad Gateway</html>");
+        assert!(!unavailable.is_rejected());
+    }
+
+    #[test]
+    fn test_server_time() {
+        let mut headers = HeaderMap::new();
+        headers.insert(DATE, HeaderValue::from_static("Sat, 29 Oct 2022 00:59:33 GMT"));
+        let error = AuthError::classify(
+            StatusCode::BAD_REQUEST,
+            &headers,
+            r#"{"error":"invalid_grant","error_description":"Invalid TOTP"}"#,
+        );
+
+        match error {
+            AuthError::InvalidTotp { server_time, .. } => {
+                assert_eq!(server_time.unwrap().to_rfc3339(), "2022-10-29T00:59:33+00:00")
+            }
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oidc_client() {
+        let server = MockServer::start().await;
+        mount_discovery(&server).await;
+
+        // Issued without the openid scope
+        let mut token = token_response("access", "");
+        token.as_object_mut().unwrap().remove("id_token");
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(header("Content-Type", "application/x-www-form-urlencoded"))
+            .and(body_string_contains("grant_type=password"))
+            .and(body_string_contains("totp=123456"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(token))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .set_body_json(json!({ "error": "invalid_grant", "error_description": "Token is not active" })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/revoke"))
+            .and(body_string_contains("token_type_hint=refresh_token"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = OidcClient::discover(&format!("{}/", server.uri()), "website").await.unwrap();
+        assert_eq!(client.metadata().token_endpoint, format!("{}/token", server.uri()));
+
+        let token = client.password_grant("xfileFIN", "password", "123456", "gportal").await.unwrap();
+        assert_eq!(token.refresh_token, "refresh");
+        assert_eq!(token.id_token, "");
+
+        let err = client.refresh_grant("refresh").await.unwrap_err();
+        assert!(matches!(err, AuthError::SessionExpired(_)));
+
+        client.revoke("refresh").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_issuer_mismatch() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": "https://evil.example.com",
+                "token_endpoint": "https://evil.example.com/token",
+                "jwks_uri": "https://evil.example.com/certs",
+            })))
+            .mount(&server)
+            .await;
+
+        let err = OidcClient::discover(&server.uri(), "website").await.err().unwrap();
+        assert!(matches!(err, AuthError::Discovery(_)));
+    }
 }
@@ -0,0 +1,116 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Notification that hasn't been delivered yet, queued before the first attempt and kept for the retries
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub transaction_id: String,
+    pub sink: String,
+    /// Failed delivery attempts so far
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: String,
+    /// Given up after `max_attempts`, kept around for inspection
+    pub dead: bool,
+}
+
+impl OutboxEntry {
+    pub fn new(transaction_id: &str, sink: &str) -> Self {
+        OutboxEntry {
+            transaction_id: transaction_id.to_string(),
+            sink: sink.to_string(),
+            attempts: 0,
+            next_attempt_at: Utc::now(),
+            last_error: String::new(),
+            dead: false,
+        }
+    }
+
+    /// Records a failed attempt and schedules the next one, or moves the entry to the dead letters
+    pub fn failed(&mut self, error: &str, policy: &RetryPolicy, now: DateTime<Utc>) {
+        self.attempts += 1;
+        self.last_error = error.to_string();
+
+        if self.attempts >= policy.max_attempts {
+            self.dead = true;
+        } else {
+            self.next_attempt_at = now + policy.delay(self.attempts);
+        }
+    }
+}
+
+/// `[retry]` section of the config
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts including the first one before a notification ends up in the dead letters
+    pub max_attempts: u32,
+    pub initial_delay_secs: u64,
+    pub max_delay_secs: u64,
+    /// Share of the delay that is randomized, `0.2` spreads retries over 80-100% of the delay
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 8,
+            initial_delay_secs: 60,
+            max_delay_secs: 6 * 60 * 60,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt after `attempts` failed ones, doubling every time
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(32);
+        let delay = self
+            .initial_delay_secs
+            .saturating_mul(1u64 << exponent)
+            .min(self.max_delay_secs) as f64;
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::thread_rng().gen_range(1.0 - jitter..=1.0)
+        } else {
+            1.0
+        };
+
+        Duration::milliseconds((delay * factor * 1000.0) as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_delay_secs: 60,
+            max_delay_secs: 200,
+            jitter: 0.0,
+        };
+
+        assert_eq!(policy.delay(1), Duration::seconds(60));
+        assert_eq!(policy.delay(2), Duration::seconds(120));
+        assert_eq!(policy.delay(3), Duration::seconds(200));
+        assert_eq!(policy.delay(100), Duration::seconds(200));
+
+        let jittered = RetryPolicy { jitter: 0.5, ..policy.clone() }.delay(2);
+        assert!(jittered >= Duration::seconds(60) && jittered <= Duration::seconds(120));
+
+        let now = Utc::now();
+        let mut entry = OutboxEntry::new("14500001", "discord");
+        entry.failed("timeout", &policy, now);
+        assert_eq!(entry.next_attempt_at, now + Duration::seconds(60));
+        entry.failed("timeout", &policy, now);
+        entry.failed("timeout", &policy, now);
+        assert!(entry.dead);
+        assert_eq!(entry.attempts, 3);
+    }
+}
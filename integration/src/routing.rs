@@ -0,0 +1,178 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::notifier::DonationContext;
+
+/// Entry of the `[[routes]]` list in the config. Every condition that is set has to match.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Route {
+    /// Matches donations of at least this amount
+    pub min_amount: Option<Decimal>,
+    /// Matches donations below this amount
+    pub max_amount: Option<Decimal>,
+    /// Matches when the purpose contains any of these, case-insensitive
+    pub keywords: Vec<String>,
+    /// Matches when the server name parsed from the purpose is any of these, case-insensitive
+    pub servers: Vec<String>,
+    /// Notifier names the donation is sent to
+    pub sinks: Vec<String>,
+    /// Named template the sinks render the donation with
+    pub template: Option<String>,
+    /// Skip the routes after this one when it matches
+    pub stop: bool,
+}
+
+impl Route {
+    pub fn matches(&self, donation: &DonationContext) -> bool {
+        let amount = donation.amount_value;
+        if self.min_amount.is_some_and(|min| amount < min) || self.max_amount.is_some_and(|max| amount >= max) {
+            return false;
+        }
+
+        if !self.keywords.is_empty() {
+            let purpose = donation.purpose.to_lowercase();
+            if !self.keywords.iter().any(|keyword| purpose.contains(&keyword.to_lowercase())) {
+                return false;
+            }
+        }
+
+        if !self.servers.is_empty() {
+            let server = match &donation.server_name {
+                Some(server) => server,
+                None => return false,
+            };
+            if !self.servers.iter().any(|s| s.eq_ignore_ascii_case(server)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Sink a donation is sent to, with the template picked by the route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub sink: String,
+    pub template: Option<String>,
+}
+
+/// Decides which sinks a donation goes to. Without routes every donation goes to every sink.
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new(routes: Vec<Route>) -> Self {
+        Router { routes }
+    }
+
+    /// Warns about routes pointing to sinks that aren't configured
+    pub fn check_sinks(&self, sinks: &[String]) {
+        for route in &self.routes {
+            for sink in route.sinks.iter().filter(|sink| !sinks.contains(sink)) {
+                warn!("Route refers to unknown notifier {}", sink);
+            }
+        }
+    }
+
+    /// Destinations of the donation among `sinks`, in route order. The first route matching a sink decides its template.
+    pub fn route(&self, donation: &DonationContext, sinks: &[String]) -> Vec<Destination> {
+        if self.routes.is_empty() {
+            return sinks
+                .iter()
+                .map(|sink| Destination {
+                    sink: sink.clone(),
+                    template: None,
+                })
+                .collect();
+        }
+
+        let mut destinations: Vec<Destination> = Vec::new();
+        for route in self.routes.iter().filter(|route| route.matches(donation)) {
+            for sink in route.sinks.iter().filter(|sink| sinks.contains(sink)) {
+                if !destinations.iter().any(|destination| &destination.sink == sink) {
+                    destinations.push(Destination {
+                        sink: sink.clone(),
+                        template: route.template.clone(),
+                    });
+                }
+            }
+
+            if route.stop {
+                break;
+            }
+        }
+
+        destinations
+    }
+
+    /// Template the donation is rendered with for `sink`
+    pub fn template_for(&self, donation: &DonationContext, sink: &str) -> Option<String> {
+        let sinks = [sink.to_string()];
+        self.route(donation, &sinks).into_iter().next().and_then(|destination| destination.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::tests::context;
+
+    fn sinks() -> Vec<String> {
+        vec!["discord".to_string(), "shoutouts".to_string(), "telegram".to_string()]
+    }
+
+    fn sink_names(destinations: Vec<Destination>) -> Vec<String> {
+        destinations.into_iter().map(|destination| destination.sink).collect()
+    }
+
+    #[test]
+    fn test_without_routes() {
+        assert_eq!(sink_names(Router::default().route(&context(), &sinks())), sinks());
+    }
+
+    #[test]
+    fn test_routes() {
+        let config: toml::Value = toml::from_str(
+            r#"
+            [[routes]]
+            min_amount = 50
+            sinks = ["shoutouts"]
+            template = "big"
+
+            [[routes]]
+            keywords = ["server fund"]
+            sinks = ["telegram"]
+            stop = true
+
+            [[routes]]
+            servers = ["LSD2"]
+            sinks = ["discord", "unknown"]
+            template = "lsd2"
+
+            [[routes]]
+            sinks = ["discord"]
+            "#,
+        )
+        .unwrap();
+        let router = Router::new(config["routes"].clone().try_into().unwrap());
+
+        // 10.00 €, purpose "soldiername: PoorGuy"
+        let mut donation = context();
+        assert_eq!(sink_names(router.route(&donation, &sinks())), vec!["discord"]);
+        assert_eq!(router.template_for(&donation, "discord"), None);
+
+        donation.amount_value = Decimal::from(50);
+        donation.server_name = Some("lsd2".to_string());
+        let destinations = router.route(&donation, &sinks());
+        assert_eq!(sink_names(destinations.clone()), vec!["shoutouts", "discord"]);
+        assert_eq!(destinations[0].template.as_deref(), Some("big"));
+        assert_eq!(router.template_for(&donation, "discord").as_deref(), Some("lsd2"));
+
+        donation.purpose = "For the Server Fund".to_string();
+        assert_eq!(sink_names(router.route(&donation, &sinks())), vec!["shoutouts", "telegram"]);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use api::Transaction;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::outbox::OutboxEntry;
+
+mod json;
+mod sqlite;
+
+pub use json::JsonStateStore;
+pub use sqlite::SqliteStateStore;
+
+const DEFAULT_JSON_PATH: &str = r#"./data/donations_state.json"#;
+const DEFAULT_SQLITE_PATH: &str = r#"./data/donations.sqlite"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationStatus {
+    Pending,
+    Sent,
+    /// Delivery failed, a retry is queued in the outbox
+    Failed,
+    /// Gave up retrying
+    DeadLetter,
+}
+
+impl NotificationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationStatus::Pending => "pending",
+            NotificationStatus::Sent => "sent",
+            NotificationStatus::Failed => "failed",
+            NotificationStatus::DeadLetter => "dead_letter",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<NotificationStatus> {
+        match value {
+            "pending" => Some(NotificationStatus::Pending),
+            "sent" => Some(NotificationStatus::Sent),
+            "failed" => Some(NotificationStatus::Failed),
+            "dead_letter" => Some(NotificationStatus::DeadLetter),
+            _ => None,
+        }
+    }
+}
+
+/// Transaction as recorded by the poller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeenTransaction {
+    pub transaction: Transaction,
+    /// VIP days computed when the donation was seen, `None` for anything that isn't a donation
+    pub vip_days: Option<i64>,
+    pub seen_at: DateTime<Utc>,
+    /// Notification status by sink name
+    #[serde(default)]
+    pub notifications: BTreeMap<String, NotificationStatus>,
+}
+
+/// Persistent state of the donation poller, so donations are announced exactly once over restarts.
+pub trait StateStore: Send {
+    /// Highest numeric transaction ID recorded so far
+    fn high_water_mark(&self) -> Result<Option<u64>, anyhow::Error>;
+
+    /// Whether the first run has been handled, also when the history was still empty then
+    fn is_initialised(&self) -> Result<bool, anyhow::Error>;
+
+    fn mark_initialised(&mut self) -> Result<(), anyhow::Error>;
+
+    fn is_seen(&self, transaction_id: &str) -> Result<bool, anyhow::Error>;
+
+    fn record_transaction(&mut self, transaction: &Transaction, vip_days: Option<i64>) -> Result<(), anyhow::Error>;
+
+    fn set_notification_status(
+        &mut self,
+        transaction_id: &str,
+        sink: &str,
+        status: NotificationStatus,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Records the transaction with a pending notification and an outbox entry per sink in one go,
+    /// so nothing is lost when the process dies before the notifications are sent
+    fn queue_notifications(
+        &mut self,
+        transaction: &Transaction,
+        vip_days: Option<i64>,
+        entries: &[OutboxEntry],
+    ) -> Result<(), anyhow::Error>;
+
+    fn get_transaction(&self, transaction_id: &str) -> Result<Option<SeenTransaction>, anyhow::Error>;
+
+    /// Every recorded transaction, newest first
+    fn transactions(&self) -> Result<Vec<SeenTransaction>, anyhow::Error>;
+
+    /// Adds or updates the outbox entry of an undelivered notification
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), anyhow::Error>;
+
+    fn remove_outbox_entry(&mut self, transaction_id: &str, sink: &str) -> Result<(), anyhow::Error>;
+
+    /// Outbox entries that are still retried and due at `now`, oldest first
+    fn due_outbox_entries(&self, now: DateTime<Utc>) -> Result<Vec<OutboxEntry>, anyhow::Error>;
+
+    /// Outbox entries that ran out of attempts
+    fn dead_letters(&self) -> Result<Vec<OutboxEntry>, anyhow::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    Json,
+    Sqlite,
+}
+
+/// `[state]` section of the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StateConfig {
+    pub backend: StateBackend,
+    /// Defaults to `./data/donations_state.json` or `./data/donations.sqlite` depending on the backend
+    pub path: Option<String>,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            backend: StateBackend::Json,
+            path: None,
+        }
+    }
+}
+
+impl StateConfig {
+    /// Same backend with the default file in `./data/<account>/`, accounts never share a state
+    pub fn for_account(&self, account: &str) -> StateConfig {
+        let default_path = match self.backend {
+            StateBackend::Json => DEFAULT_JSON_PATH,
+            StateBackend::Sqlite => DEFAULT_SQLITE_PATH,
+        };
+        let file_name = Path::new(default_path).file_name().unwrap().to_string_lossy();
+
+        StateConfig {
+            backend: self.backend,
+            path: Some(format!("./data/{}/{}", account, file_name)),
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn StateStore>, anyhow::Error> {
+        match self.backend {
+            StateBackend::Json => {
+                let path = self.path.as_deref().unwrap_or(DEFAULT_JSON_PATH);
+                info!("Using JSON donation state at {}", path);
+                Ok(Box::new(JsonStateStore::open(path)?))
+            }
+            StateBackend::Sqlite => {
+                let path = self.path.as_deref().unwrap_or(DEFAULT_SQLITE_PATH);
+                info!("Using SQLite donation state at {}", path);
+                Ok(Box::new(SqliteStateStore::open(path)?))
+            }
+        }
+    }
+}
+
+fn numeric_id(transaction_id: &str) -> Option<u64> {
+    transaction_id.parse::<u64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::Region;
+
+    fn transaction(id: &str) -> Transaction {
+        Transaction::from_row(
+            &[id, "Donation from poorGuy - Purpose: PoorGuy", "1.49 €", "2022-10-01T21:50:01+02:00"],
+            Region::Eur,
+        )
+        .unwrap()
+    }
+
+    /// Shared checks every backend has to pass
+    pub fn check_store(store: &mut dyn StateStore) {
+        assert_eq!(store.high_water_mark().unwrap(), None);
+        assert!(!store.is_seen("14500001").unwrap());
+        assert!(!store.is_initialised().unwrap());
+        store.mark_initialised().unwrap();
+        assert!(store.is_initialised().unwrap());
+
+        store.record_transaction(&transaction("14500001"), Some(9)).unwrap();
+        store.record_transaction(&transaction("14500000"), Some(9)).unwrap();
+        store.set_notification_status("14500001", "discord", NotificationStatus::Failed).unwrap();
+        store.set_notification_status("14500001", "discord", NotificationStatus::Sent).unwrap();
+
+        assert_eq!(store.high_water_mark().unwrap(), Some(14500001));
+        assert!(store.is_seen("14500000").unwrap());
+        assert!(store.is_seen("14500001").unwrap());
+        assert!(!store.is_seen("14500002").unwrap());
+
+        let seen = store.get_transaction("14500001").unwrap().unwrap();
+        assert_eq!(seen.vip_days, Some(9));
+        assert_eq!(seen.notifications.get("discord"), Some(&NotificationStatus::Sent));
+
+        let ids: Vec<String> = store.transactions().unwrap().into_iter().map(|t| t.transaction.id).collect();
+        assert_eq!(ids, vec!["14500001", "14500000"]);
+
+        let now = Utc::now();
+        let mut retry = OutboxEntry::new("14500000", "discord");
+        retry.next_attempt_at = now;
+        let mut dead = OutboxEntry::new("14500001", "discord");
+        dead.dead = true;
+        store.save_outbox_entry(&retry).unwrap();
+        store.save_outbox_entry(&dead).unwrap();
+
+        assert_eq!(store.due_outbox_entries(now).unwrap(), vec![retry.clone()]);
+        assert!(store.due_outbox_entries(now - chrono::Duration::seconds(1)).unwrap().is_empty());
+        assert_eq!(store.dead_letters().unwrap(), vec![dead]);
+
+        retry.attempts = 2;
+        store.save_outbox_entry(&retry).unwrap();
+        assert_eq!(store.due_outbox_entries(now).unwrap()[0].attempts, 2);
+
+        store.remove_outbox_entry("14500000", "discord").unwrap();
+        assert!(store.due_outbox_entries(now).unwrap().is_empty());
+
+        let queued = OutboxEntry::new("14500002", "discord");
+        store.queue_notifications(&transaction("14500002"), Some(9), std::slice::from_ref(&queued)).unwrap();
+        assert_eq!(store.high_water_mark().unwrap(), Some(14500002));
+        assert_eq!(store.due_outbox_entries(queued.next_attempt_at).unwrap(), vec![queued]);
+        let seen = store.get_transaction("14500002").unwrap().unwrap();
+        assert_eq!(seen.notifications.get("discord"), Some(&NotificationStatus::Pending));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use api::Transaction;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{numeric_id, NotificationStatus, SeenTransaction, StateStore};
+use crate::outbox::OutboxEntry;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct JsonState {
+    high_water_mark: Option<u64>,
+    initialised: bool,
+    transactions: BTreeMap<String, SeenTransaction>,
+    outbox: Vec<OutboxEntry>,
+}
+
+impl JsonState {
+    fn record_transaction(&mut self, transaction: &Transaction, vip_days: Option<i64>) {
+        if let Some(id) = numeric_id(&transaction.id) {
+            self.high_water_mark = Some(self.high_water_mark.map_or(id, |mark| mark.max(id)));
+        }
+
+        self.transactions
+            .entry(transaction.id.clone())
+            .or_insert_with(|| SeenTransaction {
+                transaction: transaction.clone(),
+                vip_days,
+                seen_at: Utc::now(),
+                notifications: BTreeMap::new(),
+            });
+    }
+
+    fn set_notification_status(
+        &mut self,
+        transaction_id: &str,
+        sink: &str,
+        status: NotificationStatus,
+    ) -> Result<(), anyhow::Error> {
+        let seen = self
+            .transactions
+            .get_mut(transaction_id)
+            .ok_or_else(|| anyhow::anyhow!("Transaction {} has not been recorded", transaction_id))?;
+        seen.notifications.insert(sink.to_string(), status);
+
+        Ok(())
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) {
+        let existing = self
+            .outbox
+            .iter_mut()
+            .find(|e| e.transaction_id == entry.transaction_id && e.sink == entry.sink);
+
+        match existing {
+            Some(existing) => *existing = entry.clone(),
+            None => self.outbox.push(entry.clone()),
+        }
+    }
+}
+
+/// State kept in a single JSON file, rewritten on every change.
+pub struct JsonStateStore {
+    path: String,
+    state: JsonState,
+}
+
+impl JsonStateStore {
+    pub fn open(path: &str) -> Result<JsonStateStore, anyhow::Error> {
+        let state = if Path::new(path).exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            JsonState::default()
+        };
+
+        Ok(JsonStateStore {
+            path: path.to_string(),
+            state,
+        })
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        if let Some(p) = Path::new(&self.path).parent() {
+            fs::create_dir_all(p)?
+        };
+
+        // A crash while writing would leave truncated JSON, the rename replaces the file in one step
+        let tmp_path = format!("{}.tmp", self.path);
+        fs::write(&tmp_path, serde_json::to_string_pretty(&self.state)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl StateStore for JsonStateStore {
+    fn high_water_mark(&self) -> Result<Option<u64>, anyhow::Error> {
+        Ok(self.state.high_water_mark)
+    }
+
+    fn is_initialised(&self) -> Result<bool, anyhow::Error> {
+        // States written before the flag existed have a high-water mark instead
+        Ok(self.state.initialised || self.state.high_water_mark.is_some())
+    }
+
+    fn mark_initialised(&mut self) -> Result<(), anyhow::Error> {
+        self.state.initialised = true;
+
+        self.save()
+    }
+
+    fn is_seen(&self, transaction_id: &str) -> Result<bool, anyhow::Error> {
+        let below_mark = match (numeric_id(transaction_id), self.state.high_water_mark) {
+            (Some(id), Some(mark)) => id <= mark,
+            _ => false,
+        };
+
+        Ok(below_mark || self.state.transactions.contains_key(transaction_id))
+    }
+
+    fn record_transaction(&mut self, transaction: &Transaction, vip_days: Option<i64>) -> Result<(), anyhow::Error> {
+        self.state.record_transaction(transaction, vip_days);
+
+        self.save()
+    }
+
+    fn set_notification_status(
+        &mut self,
+        transaction_id: &str,
+        sink: &str,
+        status: NotificationStatus,
+    ) -> Result<(), anyhow::Error> {
+        self.state.set_notification_status(transaction_id, sink, status)?;
+
+        self.save()
+    }
+
+    fn queue_notifications(
+        &mut self,
+        transaction: &Transaction,
+        vip_days: Option<i64>,
+        entries: &[OutboxEntry],
+    ) -> Result<(), anyhow::Error> {
+        self.state.record_transaction(transaction, vip_days);
+        for entry in entries {
+            self.state.set_notification_status(&transaction.id, &entry.sink, NotificationStatus::Pending)?;
+            self.state.save_outbox_entry(entry);
+        }
+
+        self.save()
+    }
+
+    fn get_transaction(&self, transaction_id: &str) -> Result<Option<SeenTransaction>, anyhow::Error> {
+        Ok(self.state.transactions.get(transaction_id).cloned())
+    }
+
+    fn transactions(&self) -> Result<Vec<SeenTransaction>, anyhow::Error> {
+        let mut transactions: Vec<SeenTransaction> = self.state.transactions.values().cloned().collect();
+        transactions.sort_by(|a, b| {
+            let a = &a.transaction;
+            let b = &b.transaction;
+            b.time.cmp(&a.time).then_with(|| numeric_id(&b.id).cmp(&numeric_id(&a.id)))
+        });
+
+        Ok(transactions)
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), anyhow::Error> {
+        self.state.save_outbox_entry(entry);
+
+        self.save()
+    }
+
+    fn remove_outbox_entry(&mut self, transaction_id: &str, sink: &str) -> Result<(), anyhow::Error> {
+        self.state
+            .outbox
+            .retain(|e| !(e.transaction_id == transaction_id && e.sink == sink));
+
+        self.save()
+    }
+
+    fn due_outbox_entries(&self, now: DateTime<Utc>) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+        let mut entries: Vec<OutboxEntry> = self
+            .state
+            .outbox
+            .iter()
+            .filter(|e| !e.dead && e.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        entries.sort_by_key(|e| e.next_attempt_at);
+
+        Ok(entries)
+    }
+
+    fn dead_letters(&self) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+        Ok(self.state.outbox.iter().filter(|e| e.dead).cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_store() {
+        let path = std::env::temp_dir().join(format!("gportal-json-state-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut store = JsonStateStore::open(path).unwrap();
+        super::super::tests::check_store(&mut store);
+
+        let reopened = JsonStateStore::open(path).unwrap();
+        assert_eq!(reopened.high_water_mark().unwrap(), Some(14500002));
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use api::{Money, Transaction, TransactionKind};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::{numeric_id, NotificationStatus, SeenTransaction, StateStore};
+use crate::outbox::OutboxEntry;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS transactions (
+    id          TEXT PRIMARY KEY,
+    numeric_id  INTEGER,
+    kind        TEXT NOT NULL,
+    description TEXT NOT NULL,
+    amount      TEXT NOT NULL,
+    currency    TEXT NOT NULL,
+    time        TEXT NOT NULL,
+    timestamp   INTEGER NOT NULL,
+    vip_days    INTEGER,
+    seen_at     TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS notifications (
+    transaction_id TEXT NOT NULL REFERENCES transactions(id),
+    sink           TEXT NOT NULL,
+    status         TEXT NOT NULL,
+    updated_at     TEXT NOT NULL,
+    PRIMARY KEY (transaction_id, sink)
+);
+
+CREATE TABLE IF NOT EXISTS meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS outbox (
+    transaction_id  TEXT NOT NULL REFERENCES transactions(id),
+    sink            TEXT NOT NULL,
+    attempts        INTEGER NOT NULL,
+    next_attempt_at TEXT NOT NULL,
+    next_attempt    INTEGER NOT NULL,
+    last_error      TEXT NOT NULL,
+    dead            INTEGER NOT NULL,
+    PRIMARY KEY (transaction_id, sink)
+);
+"#;
+
+const SELECT_OUTBOX: &str = r#"
+SELECT transaction_id, sink, attempts, next_attempt_at, last_error, dead FROM outbox
+"#;
+
+const SELECT_TRANSACTION: &str = r#"
+SELECT id, kind, description, amount, currency, time, vip_days, seen_at FROM transactions
+"#;
+
+/// State kept in an embedded SQLite database, handy for querying the donation history.
+pub struct SqliteStateStore {
+    connection: Connection,
+}
+
+impl SqliteStateStore {
+    pub fn open(path: &str) -> Result<SqliteStateStore, anyhow::Error> {
+        if let Some(p) = Path::new(path).parent() {
+            fs::create_dir_all(p)?
+        };
+
+        SqliteStateStore::with_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Result<SqliteStateStore, anyhow::Error> {
+        SqliteStateStore::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<SqliteStateStore, anyhow::Error> {
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(SqliteStateStore { connection })
+    }
+
+    fn read_transaction(row: &Row) -> Result<SeenTransaction, anyhow::Error> {
+        let kind: String = row.get(1)?;
+        let amount: String = row.get(3)?;
+        let time: String = row.get(5)?;
+        let seen_at: String = row.get(7)?;
+
+        Ok(SeenTransaction {
+            transaction: Transaction {
+                id: row.get(0)?,
+                description: row.get(2)?,
+                amount: Money::new(amount.parse()?, row.get::<_, String>(4)?),
+                time: DateTime::parse_from_rfc3339(&time)?,
+                kind: serde_json::from_value(serde_json::Value::String(kind))?,
+            },
+            vip_days: row.get(6)?,
+            seen_at: seen_at.parse::<DateTime<Utc>>()?,
+            notifications: BTreeMap::new(),
+        })
+    }
+
+    fn with_notifications(&self, mut seen: SeenTransaction) -> Result<SeenTransaction, anyhow::Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT sink, status FROM notifications WHERE transaction_id = ?1")?;
+        let mut rows = statement.query(params![seen.transaction.id])?;
+
+        while let Some(row) = rows.next()? {
+            let status: String = row.get(1)?;
+            if let Some(status) = NotificationStatus::parse(&status) {
+                seen.notifications.insert(row.get(0)?, status);
+            }
+        }
+
+        Ok(seen)
+    }
+
+    fn read_outbox_entry(row: &Row) -> Result<OutboxEntry, anyhow::Error> {
+        let next_attempt_at: String = row.get(3)?;
+
+        Ok(OutboxEntry {
+            transaction_id: row.get(0)?,
+            sink: row.get(1)?,
+            attempts: row.get(2)?,
+            next_attempt_at: next_attempt_at.parse::<DateTime<Utc>>()?,
+            last_error: row.get(4)?,
+            dead: row.get(5)?,
+        })
+    }
+
+    fn query_outbox<P: rusqlite::Params>(&self, filter: &str, params: P) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+        let mut statement = self.connection.prepare(&format!("{} {}", SELECT_OUTBOX, filter))?;
+        let mut rows = statement.query(params)?;
+
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next()? {
+            entries.push(SqliteStateStore::read_outbox_entry(row)?);
+        }
+
+        Ok(entries)
+    }
+}
+
+fn kind_to_str(kind: TransactionKind) -> Result<String, anyhow::Error> {
+    match serde_json::to_value(kind)? {
+        serde_json::Value::String(kind) => Ok(kind),
+        other => Err(anyhow::anyhow!("Unexpected transaction kind {}", other)),
+    }
+}
+
+impl StateStore for SqliteStateStore {
+    fn high_water_mark(&self) -> Result<Option<u64>, anyhow::Error> {
+        let mark: Option<i64> = self
+            .connection
+            .query_row("SELECT MAX(numeric_id) FROM transactions", [], |row| row.get(0))?;
+
+        Ok(mark.map(|mark| mark as u64))
+    }
+
+    fn is_initialised(&self) -> Result<bool, anyhow::Error> {
+        let count: i64 = self
+            .connection
+            .query_row("SELECT COUNT(*) FROM meta WHERE key = 'initialised'", [], |row| row.get(0))?;
+
+        // Databases created before the flag existed have a high-water mark instead
+        Ok(count > 0 || self.high_water_mark()?.is_some())
+    }
+
+    fn mark_initialised(&mut self) -> Result<(), anyhow::Error> {
+        self.connection
+            .execute("INSERT OR IGNORE INTO meta (key, value) VALUES ('initialised', 'true')", [])?;
+
+        Ok(())
+    }
+
+    fn is_seen(&self, transaction_id: &str) -> Result<bool, anyhow::Error> {
+        if let (Some(id), Some(mark)) = (numeric_id(transaction_id), self.high_water_mark()?) {
+            if id <= mark {
+                return Ok(true);
+            }
+        }
+
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM transactions WHERE id = ?1",
+            params![transaction_id],
+            |row| row.get(0),
+        )?;
+
+        Ok(count > 0)
+    }
+
+    fn record_transaction(&mut self, transaction: &Transaction, vip_days: Option<i64>) -> Result<(), anyhow::Error> {
+        self.connection.execute(
+            "INSERT OR IGNORE INTO transactions (id, numeric_id, kind, description, amount, currency, time, timestamp, vip_days, seen_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                transaction.id,
+                numeric_id(&transaction.id).map(|id| id as i64),
+                kind_to_str(transaction.kind)?,
+                transaction.description,
+                transaction.amount.amount.to_string(),
+                transaction.amount.currency,
+                transaction.time.to_rfc3339(),
+                transaction.time.timestamp(),
+                vip_days,
+                Utc::now().to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn set_notification_status(
+        &mut self,
+        transaction_id: &str,
+        sink: &str,
+        status: NotificationStatus,
+    ) -> Result<(), anyhow::Error> {
+        self.connection.execute(
+            "INSERT INTO notifications (transaction_id, sink, status, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (transaction_id, sink) DO UPDATE SET status = excluded.status, updated_at = excluded.updated_at",
+            params![transaction_id, sink, status.as_str(), Utc::now().to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    fn queue_notifications(
+        &mut self,
+        transaction: &Transaction,
+        vip_days: Option<i64>,
+        entries: &[OutboxEntry],
+    ) -> Result<(), anyhow::Error> {
+        self.connection.execute_batch("BEGIN")?;
+        let result = (|| {
+            self.record_transaction(transaction, vip_days)?;
+            for entry in entries {
+                self.set_notification_status(&transaction.id, &entry.sink, NotificationStatus::Pending)?;
+                self.save_outbox_entry(entry)?;
+            }
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => self.connection.execute_batch("COMMIT")?,
+            Err(_) => self.connection.execute_batch("ROLLBACK")?,
+        }
+        result
+    }
+
+    fn get_transaction(&self, transaction_id: &str) -> Result<Option<SeenTransaction>, anyhow::Error> {
+        let seen = self
+            .connection
+            .query_row(
+                &format!("{} WHERE id = ?1", SELECT_TRANSACTION),
+                params![transaction_id],
+                |row| Ok(SqliteStateStore::read_transaction(row)),
+            )
+            .optional()?
+            .transpose()?;
+
+        seen.map(|seen| self.with_notifications(seen)).transpose()
+    }
+
+    fn transactions(&self) -> Result<Vec<SeenTransaction>, anyhow::Error> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("{} ORDER BY timestamp DESC, numeric_id DESC", SELECT_TRANSACTION))?;
+        let mut rows = statement.query([])?;
+
+        let mut transactions = Vec::new();
+        while let Some(row) = rows.next()? {
+            transactions.push(self.with_notifications(SqliteStateStore::read_transaction(row)?)?);
+        }
+
+        Ok(transactions)
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), anyhow::Error> {
+        self.connection.execute(
+            "INSERT INTO outbox (transaction_id, sink, attempts, next_attempt_at, next_attempt, last_error, dead)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (transaction_id, sink) DO UPDATE SET attempts = excluded.attempts,
+                 next_attempt_at = excluded.next_attempt_at, next_attempt = excluded.next_attempt,
+                 last_error = excluded.last_error, dead = excluded.dead",
+            params![
+                entry.transaction_id,
+                entry.sink,
+                entry.attempts,
+                entry.next_attempt_at.to_rfc3339(),
+                entry.next_attempt_at.timestamp_millis(),
+                entry.last_error,
+                entry.dead,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn remove_outbox_entry(&mut self, transaction_id: &str, sink: &str) -> Result<(), anyhow::Error> {
+        self.connection.execute(
+            "DELETE FROM outbox WHERE transaction_id = ?1 AND sink = ?2",
+            params![transaction_id, sink],
+        )?;
+
+        Ok(())
+    }
+
+    fn due_outbox_entries(&self, now: DateTime<Utc>) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+        self.query_outbox(
+            "WHERE dead = 0 AND next_attempt <= ?1 ORDER BY next_attempt",
+            params![now.timestamp_millis()],
+        )
+    }
+
+    fn dead_letters(&self) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+        self.query_outbox("WHERE dead = 1 ORDER BY next_attempt", [])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_store() {
+        let mut store = SqliteStateStore::in_memory().unwrap();
+        super::super::tests::check_store(&mut store);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::openid::Token;
+
+const TOKEN_PATH: &str = r#"./data/gportal_token.enc"#;
+const FILE_VERSION: u32 = 1;
+
+/// Token as persisted between restarts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredToken {
+    /// Account the token belongs to, a token of another account is never reused
+    pub username: String,
+    pub token: Token,
+    pub fetch_time: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Keeps the OAuth token encrypted at rest with AES-256-GCM, so restarts can reuse the session
+/// instead of logging in with the password again.
+pub struct TokenStore {
+    path: String,
+    cipher: Aes256Gcm,
+}
+
+impl TokenStore {
+    /// `key` is a hex encoded 32 byte key, e.g. from `openssl rand -hex 32`
+    pub fn new(path: &str, key: &str) -> Result<TokenStore, anyhow::Error> {
+        let key = hex::decode(key.trim()).map_err(|_| anyhow::anyhow!("Token key must be hex encoded"))?;
+        if key.len() != 32 {
+            return Err(anyhow::anyhow!("Token key must be 32 bytes, got {}", key.len()));
+        }
+
+        Ok(TokenStore {
+            path: path.to_string(),
+            cipher: Aes256Gcm::new_from_slice(&key)?,
+        })
+    }
+
+    /// Store configured with `GPORTAL_TOKEN_KEY` and `GPORTAL_TOKEN_PATH`, `None` if persistence isn't enabled
+    pub fn from_env() -> Result<Option<TokenStore>, anyhow::Error> {
+        let path = dotenv::var("GPORTAL_TOKEN_PATH").unwrap_or(TOKEN_PATH.to_string());
+        TokenStore::from_env_at(&path)
+    }
+
+    /// Store at `path` with the key from `GPORTAL_TOKEN_KEY`, `None` if persistence isn't enabled
+    pub fn from_env_at(path: &str) -> Result<Option<TokenStore>, anyhow::Error> {
+        let key = match dotenv::var("GPORTAL_TOKEN_KEY") {
+            Ok(key) if !key.is_empty() => key,
+            _ => return Ok(None),
+        };
+
+        TokenStore::new(path, &key).map(Some)
+    }
+
+    pub fn load(&self, username: &str) -> Result<Option<StoredToken>, anyhow::Error> {
+        if !Path::new(&self.path).exists() {
+            return Ok(None);
+        }
+
+        let file: EncryptedFile = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+        if file.version != FILE_VERSION {
+            return Err(anyhow::anyhow!("Unsupported token file version {}", file.version));
+        }
+
+        let nonce = base64::decode(&file.nonce)?;
+        if nonce.len() != 12 {
+            return Err(anyhow::anyhow!("Token file has an invalid nonce"));
+        }
+        let ciphertext = base64::decode(&file.ciphertext)?;
+
+        // The username is authenticated along the token, so a file of another account fails to decrypt
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: username.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt the stored token, wrong key or account"))?;
+
+        Ok(Some(serde_json::from_slice(&plaintext)?))
+    }
+
+    pub fn save(&self, token: &StoredToken) -> Result<(), anyhow::Error> {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let plaintext = serde_json::to_vec(token)?;
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: token.username.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt the token"))?;
+
+        let file = EncryptedFile {
+            version: FILE_VERSION,
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        };
+
+        if let Some(p) = Path::new(&self.path).parent() {
+            fs::create_dir_all(p)?
+        };
+
+        // Written next to the old file and renamed over it, so a crash keeps the previous token
+        let tmp_path = format!("{}.tmp", self.path);
+        fs::write(&tmp_path, serde_json::to_string_pretty(&file)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<(), anyhow::Error> {
+        if Path::new(&self.path).exists() {
+            fs::remove_file(&self.path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openid::tests::token_response;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn token() -> Token {
+        serde_json::from_value(token_response("access", "id")).unwrap()
+    }
+
+    #[test]
+    fn test_token_store() {
+        let path = std::env::temp_dir().join(format!("gportal-token-{}.enc", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let store = TokenStore::new(path, KEY).unwrap();
+        assert!(store.load("xfileFIN").unwrap().is_none());
+
+        store
+            .save(&StoredToken {
+                username: "xfileFIN".to_string(),
+                token: token(),
+                fetch_time: Utc::now(),
+            })
+            .unwrap();
+
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+        let content = fs::read_to_string(path).unwrap();
+        assert!(!content.contains("refresh"));
+
+        let loaded = store.load("xfileFIN").unwrap().unwrap();
+        assert_eq!(loaded.token.refresh_token, "refresh");
+
+        assert!(store.load("someoneElse").is_err());
+        let other_key = TokenStore::new(path, &KEY.replace("00", "ff")).unwrap();
+        assert!(other_key.load("xfileFIN").is_err());
+
+        store.clear().unwrap();
+        assert!(store.load("xfileFIN").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(TokenStore::new("token.enc", "not hex").is_err());
+        assert!(TokenStore::new("token.enc", "0011").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::{DateTime, Duration, Utc};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const DEFAULT_DIGITS: usize = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+/// TOTP parameters of the account, from a base32 secret or an `otpauth://totp/...` URI
+#[derive(Debug, Clone)]
+pub struct TotpConfig {
+    secret: Vec<u8>,
+    digits: usize,
+    period: u64,
+    algorithm: Algorithm,
+}
+
+impl TotpConfig {
+    pub fn parse(value: &str) -> Result<TotpConfig, anyhow::Error> {
+        let value = value.trim();
+        if value.starts_with("otpauth://") {
+            return TotpConfig::from_uri(value);
+        }
+
+        TotpConfig::new(decode_secret(value)?, DEFAULT_DIGITS, DEFAULT_PERIOD, Algorithm::SHA1)
+    }
+
+    /// Secret from `TOTP_SECRET`, with `TOTP_DIGITS`, `TOTP_PERIOD` and `TOTP_ALGORITHM` overriding its parameters
+    pub fn from_env(secret: &str) -> Result<TotpConfig, anyhow::Error> {
+        let totp = TotpConfig::parse(secret)?;
+
+        let digits = match dotenv::var("TOTP_DIGITS") {
+            Ok(digits) => digits.parse()?,
+            Err(_) => totp.digits,
+        };
+        let period = match dotenv::var("TOTP_PERIOD") {
+            Ok(period) => period.parse()?,
+            Err(_) => totp.period,
+        };
+        let algorithm = match dotenv::var("TOTP_ALGORITHM") {
+            Ok(algorithm) => parse_algorithm(&algorithm)?,
+            Err(_) => totp.algorithm,
+        };
+
+        TotpConfig::new(totp.secret, digits, period, algorithm)
+    }
+
+    fn new(secret: Vec<u8>, digits: usize, period: u64, algorithm: Algorithm) -> Result<TotpConfig, anyhow::Error> {
+        // Validates the parameters once, so generating codes can't fail later
+        TOTP::new(algorithm, digits, 1, period, secret.clone())
+            .map_err(|err| anyhow::anyhow!("Invalid TOTP parameters: {:?}", err))?;
+        if period == 0 {
+            return Err(anyhow::anyhow!("TOTP period must be positive"));
+        }
+
+        Ok(TotpConfig {
+            secret,
+            digits,
+            period,
+            algorithm,
+        })
+    }
+
+    fn from_uri(uri: &str) -> Result<TotpConfig, anyhow::Error> {
+        let url = reqwest::Url::parse(uri)?;
+        if url.host_str() != Some("totp") {
+            return Err(anyhow::anyhow!("Only otpauth://totp URIs are supported"));
+        }
+
+        let mut secret = None;
+        let mut digits = DEFAULT_DIGITS;
+        let mut period = DEFAULT_PERIOD;
+        let mut algorithm = Algorithm::SHA1;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "secret" => secret = Some(decode_secret(&value)?),
+                "digits" => digits = value.parse()?,
+                "period" => period = value.parse()?,
+                "algorithm" => algorithm = parse_algorithm(&value)?,
+                _ => (),
+            }
+        }
+
+        let secret = secret.ok_or_else(|| anyhow::anyhow!("otpauth URI has no secret"))?;
+        TotpConfig::new(secret, digits, period, algorithm)
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::seconds(self.period as i64)
+    }
+
+    /// Code of the time step `time` falls in
+    pub fn code_at(&self, time: DateTime<Utc>) -> String {
+        TOTP::new(self.algorithm, self.digits, 1, self.period, self.secret.clone())
+            .expect("TOTP parameters are validated on creation")
+            .generate(time.timestamp().max(0) as u64)
+    }
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let secret = secret.replace(' ', "").to_uppercase();
+    Secret::Encoded(secret)
+        .to_bytes()
+        .map_err(|_| anyhow::anyhow!("TOTP Secret was invalid"))
+}
+
+fn parse_algorithm(value: &str) -> Result<Algorithm, anyhow::Error> {
+    match value.to_uppercase().as_str() {
+        "SHA1" => Ok(Algorithm::SHA1),
+        "SHA256" => Ok(Algorithm::SHA256),
+        "SHA512" => Ok(Algorithm::SHA512),
+        other => Err(anyhow::anyhow!("Unknown TOTP algorithm {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // RFC 6238 test secret "12345678901234567890"
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn test_rfc6238_codes() {
+        let time = Utc.timestamp_opt(59, 0).unwrap();
+
+        let totp = TotpConfig::parse(SECRET).unwrap();
+        assert_eq!(totp.code_at(time), "287082");
+
+        let uri = format!("otpauth://totp/G-Portal:xfileFIN?secret={}&digits=8&period=30&algorithm=SHA1", SECRET);
+        let totp = TotpConfig::parse(&uri).unwrap();
+        assert_eq!(totp.code_at(time), "94287082");
+        assert_eq!(totp.period(), Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(TotpConfig::parse("not base32!").is_err());
+        assert!(TotpConfig::parse("otpauth://hotp/G-Portal?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").is_err());
+        assert!(TotpConfig::parse("otpauth://totp/G-Portal?digits=6").is_err());
+        assert!(TotpConfig::parse(&format!("otpauth://totp/G-Portal?secret={}&algorithm=MD5", SECRET)).is_err());
+    }
+}